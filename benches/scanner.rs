@@ -0,0 +1,41 @@
+//! Throughput of the scanner on a large generated program. The crate has no library
+//! target, so the scanner and its tokens are compiled into the benchmark directly.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+#[cfg(test)]
+#[path = "../src/rng.rs"]
+mod rng;
+#[allow(dead_code)]
+#[path = "../src/scanner.rs"]
+mod scanner;
+#[allow(dead_code)]
+#[path = "../src/token.rs"]
+mod token;
+
+use scanner::Scanner;
+use token::TokenType as TT;
+
+fn scanner_throughput(c: &mut Criterion) {
+    let unit = "var identifier_42 = \"a string value\" + 12345.678 * (other / 3); // comment\n\
+                fun f(a, b) { return a <= b and !c; }\n";
+    let source = unit.repeat(8 * 1024 * 1024 / unit.len());
+
+    let mut group = c.benchmark_group("scanner");
+    group.throughput(Throughput::Bytes(source.len() as u64));
+    group.sample_size(10);
+    group.bench_function("throughput", |b| {
+        b.iter(|| {
+            let mut scanner = Scanner::new(&source);
+            let mut tokens = 0;
+            while scanner.scan_token().ttype != TT::EndOfFile {
+                tokens += 1;
+            }
+            tokens
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, scanner_throughput);
+criterion_main!(benches);
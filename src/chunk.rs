@@ -23,12 +23,16 @@ impl Chunk {
         self.lines.push(line);
     }
 
-    pub fn get_constant(&self, index: usize) -> Value {
+    pub fn get_constant(&self, index: usize) -> Option<Value> {
         self.constants.read_at(index)
     }
 
-    pub fn get_line(&self, index: usize) -> usize {
-        self.lines[index]
+    pub fn constants(&self) -> impl Iterator<Item = &Value> {
+        self.constants.iter()
+    }
+
+    pub fn get_line(&self, index: usize) -> Option<usize> {
+        self.lines.get(index).copied()
     }
 
     pub fn simple_instruction(&self, name: &str, offset: usize, output: &mut impl Write) -> usize {
@@ -43,36 +47,93 @@ impl Chunk {
         sign: bool,
         output: &mut impl Write,
     ) -> usize {
-        let jump = self.jump_offset(offset + 1);
+        let Some(jump) = self.jump_offset(offset + 1) else {
+            return self.missing_operand(name, output);
+        };
         let jump_to = if sign {
-            offset + 3 + jump
+            Some(offset + 3 + jump)
         } else {
-            offset - 3 + jump
+            (offset + 3).checked_sub(jump)
         };
-        writeln!(output, "{name:16} {offset:4} -> {jump_to}").unwrap();
+        match jump_to {
+            Some(jump_to) => writeln!(output, "{name:16} {offset:4} -> {jump_to}").unwrap(),
+            None => writeln!(output, "{name:16} {offset:4} -> <before start>").unwrap(),
+        }
         offset + 3
     }
 
+    /// An instruction with a local slot and a forward jump as operands, such as
+    /// `OP_FOR_ITER`, which jumps once the iterable in the slot is exhausted.
+    pub fn slot_jump_instruction(
+        &self,
+        name: &str,
+        offset: usize,
+        output: &mut impl Write,
+    ) -> usize {
+        let (Some(slot), Some(jump)) = (self.code.get(offset + 1), self.jump_offset(offset + 2))
+        else {
+            return self.missing_operand(name, output);
+        };
+        let jump_to = offset + 4 + jump;
+        writeln!(output, "{name:-16} {slot:4} -> {jump_to}").unwrap();
+        offset + 4
+    }
+
+    /// `OP_PUSH_HANDLER discard catch finally`: the number of locals the handler
+    /// discards, where a thrown value is caught and, unless 0, where a `return`
+    /// runs the `finally` block. Both targets are relative to the next instruction.
+    pub fn handler_instruction(&self, offset: usize, output: &mut impl Write) -> usize {
+        let name = "OP_PUSH_HANDLER";
+        let end = offset + 6;
+        let (Some(discard), Some(catch), Some(finally)) = (
+            self.code.get(offset + 1),
+            self.jump_offset(offset + 2),
+            self.jump_offset(offset + 4),
+        ) else {
+            return self.missing_operand(name, output);
+        };
+        write!(output, "{name:-16} {discard:4} catch -> {}", end + catch).unwrap();
+        match finally {
+            0 => writeln!(output).unwrap(),
+            finally => writeln!(output, " finally -> {}", end + finally).unwrap(),
+        }
+        end
+    }
+
     pub fn constant_instruction(
         &self,
         name: &str,
         offset: usize,
         output: &mut impl Write,
     ) -> usize {
-        let constant_index = self.code[offset + 1];
-        let value = self.constants.read_at(constant_index as usize);
-        write!(output, "{name:-16} {offset:4} '").unwrap();
-        write!(output, "{value}").unwrap();
-        writeln!(output, "'").unwrap();
+        let Some(&constant_index) = self.code.get(offset + 1) else {
+            return self.missing_operand(name, output);
+        };
+        match self.constants.read_at(constant_index as usize) {
+            Some(value) => writeln!(output, "{name:-16} {offset:4} '{value}'").unwrap(),
+            None => writeln!(
+                output,
+                "{name:-16} {offset:4} <invalid constant {constant_index}>"
+            )
+            .unwrap(),
+        }
         offset + 2
     }
 
     pub fn byte_instruction(&self, name: &str, offset: usize, output: &mut impl Write) -> usize {
-        let slot = self.code[offset + 1];
+        let Some(slot) = self.code.get(offset + 1) else {
+            return self.missing_operand(name, output);
+        };
         writeln!(output, "{name:-16} {slot:4}").unwrap();
         offset + 2
     }
 
+    /// Reports an instruction whose operands run past the end of the code.
+    fn missing_operand(&self, name: &str, output: &mut impl Write) -> usize {
+        writeln!(output, "{name:-16} <missing operand>").unwrap();
+        self.code.len()
+    }
+
     pub fn emit_byte(&mut self, byte: u8, line: usize) {
         self.write(byte, line)
     }
@@ -97,16 +158,10 @@ impl Chunk {
     pub fn write_at(&mut self, offset: usize, byte: u8) {
         self.code[offset] = byte;
     }
-    pub fn jump_offset(&self, offset: usize) -> usize {
-        ((self.code[offset] as usize) << 8) | self.code[offset + 1] as usize
-    }
-
-    pub fn clone(&self) -> Self {
-        Self {
-            code: self.code.clone(),
-            lines: self.lines.clone(),
-            constants: self.constants.clone(),
-        }
+    pub fn jump_offset(&self, offset: usize) -> Option<usize> {
+        let high = *self.code.get(offset)? as usize;
+        let low = *self.code.get(offset + 1)? as usize;
+        Some((high << 8) | low)
     }
 
     pub fn disassemble(&self, chunk_name: &str, output: &mut impl Write) {
@@ -119,13 +174,20 @@ impl Chunk {
     }
     pub fn disassemble_instruction(&self, offset: usize, output: &mut impl Write) -> usize {
         write!(output, "{offset:04}").unwrap();
+        let Some(&byte) = self.code.get(offset) else {
+            writeln!(output, " <end of code>").unwrap();
+            return offset + 1;
+        };
 
         if offset > 0 && self.lines[offset] == self.lines[offset - 1] {
             write!(output, "    | ").unwrap();
         } else {
             write!(output, " {:4} ", self.lines[offset]).unwrap();
         }
-        let instruction = &self.code[offset].into();
+        let Ok(instruction) = OpCode::try_from(byte) else {
+            writeln!(output, "Unknown opcode {byte}").unwrap();
+            return offset + 1;
+        };
         match instruction {
             OpCode::Return => self.simple_instruction("OP_RETURN", offset, output),
             OpCode::Negate => self.simple_instruction("OP_NEGATE", offset, output),
@@ -133,6 +195,32 @@ impl Chunk {
             OpCode::Substract => self.simple_instruction("OP_SUBSTRACT", offset, output),
             OpCode::Multiply => self.simple_instruction("OP_MULTIPLY", offset, output),
             OpCode::Divide => self.simple_instruction("OP_DIVIDE", offset, output),
+            OpCode::Modulo => self.simple_instruction("OP_MODULO", offset, output),
+            OpCode::IntDivide => self.simple_instruction("OP_INT_DIVIDE", offset, output),
+            OpCode::Power => self.simple_instruction("OP_POWER", offset, output),
+            OpCode::BitAnd => self.simple_instruction("OP_BIT_AND", offset, output),
+            OpCode::BitOr => self.simple_instruction("OP_BIT_OR", offset, output),
+            OpCode::BitXor => self.simple_instruction("OP_BIT_XOR", offset, output),
+            OpCode::BitNot => self.simple_instruction("OP_BIT_NOT", offset, output),
+            OpCode::ShiftLeft => self.simple_instruction("OP_SHIFT_LEFT", offset, output),
+            OpCode::ShiftRight => self.simple_instruction("OP_SHIFT_RIGHT", offset, output),
+            OpCode::LessEqual => self.simple_instruction("OP_LESS_EQUAL", offset, output),
+            OpCode::GreaterEqual => self.simple_instruction("OP_GREATER_EQUAL", offset, output),
+            OpCode::NotEqual => self.simple_instruction("OP_NOT_EQUAL", offset, output),
+            OpCode::BuildList => self.byte_instruction("OP_BUILD_LIST", offset, output),
+            OpCode::IndexGet => self.simple_instruction("OP_INDEX_GET", offset, output),
+            OpCode::IndexSet => self.simple_instruction("OP_INDEX_SET", offset, output),
+            OpCode::BuildMap => self.byte_instruction("OP_BUILD_MAP", offset, output),
+            OpCode::ForIter => self.slot_jump_instruction("OP_FOR_ITER", offset, output),
+            OpCode::Range => self.simple_instruction("OP_RANGE", offset, output),
+            OpCode::RangeInclusive => self.simple_instruction("OP_RANGE_INCLUSIVE", offset, output),
+            OpCode::In => self.simple_instruction("OP_IN", offset, output),
+            OpCode::Call => self.byte_instruction("OP_CALL", offset, output),
+            OpCode::JumpIfPassed => self.slot_jump_instruction("OP_JUMP_PASSED", offset, output),
+            OpCode::Throw => self.simple_instruction("OP_THROW", offset, output),
+            OpCode::PushHandler => self.handler_instruction(offset, output),
+            OpCode::PopHandler => self.simple_instruction("OP_POP_HANDLER", offset, output),
+            OpCode::EndFinally => self.simple_instruction("OP_END_FINALLY", offset, output),
             OpCode::Constant => self.constant_instruction("OP_CONSTANT", offset, output),
             OpCode::Nil => self.simple_instruction("OP_NIL", offset, output),
             OpCode::True => self.simple_instruction("OP_TRUE", offset, output),
@@ -144,6 +232,9 @@ impl Chunk {
             OpCode::Print => self.simple_instruction("OP_PRINT", offset, output),
             OpCode::Pop => self.simple_instruction("OP_POP", offset, output),
             OpCode::DefineGlobal => self.constant_instruction("OP_DEFINE_GLOBAL", offset, output),
+            OpCode::DefineConstant => self.constant_instruction("OP_DEFINE_CONST", offset, output),
+            OpCode::Import => self.constant_instruction("OP_IMPORT", offset, output),
+            OpCode::GetProperty => self.constant_instruction("OP_GET_PROPERTY", offset, output),
             OpCode::GetGlobal => self.constant_instruction("OP_GET_GLOBAL", offset, output),
             OpCode::SetGlobal => self.constant_instruction("OP_SET_GLOBAL", offset, output),
             OpCode::GetLocal => self.byte_instruction("OP_GET_LOCAL", offset, output),
@@ -151,16 +242,17 @@ impl Chunk {
             OpCode::JumpIfFalse => self.jump_instruction("OP_JUMP_IF_FALSE", offset, true, output),
             OpCode::Jump => self.jump_instruction("OP_JUMP", offset, true, output),
             OpCode::Loop => self.jump_instruction("OP_LOOP", offset, false, output),
+            OpCode::Stringify => self.simple_instruction("OP_STRINGIFY", offset, output),
         }
     }
 
-    pub fn read(&self, ip: usize) -> OpCode {
-        self.code[ip].into()
+    pub fn read_byte(&self, ip: usize) -> Option<u8> {
+        self.code.get(ip).copied()
     }
-    pub fn read_constant(&self, index: usize) -> Value {
+    pub fn read_constant(&self, index: usize) -> Option<Value> {
         self.get_constant(index)
     }
-    pub fn read_line(&self, index: usize) -> usize {
+    pub fn read_line(&self, index: usize) -> Option<usize> {
         self.get_line(index)
     }
 }
@@ -170,4 +262,57 @@ mod tests {
 
     use super::*;
     use rstest::*;
+
+    #[rstest]
+    fn test_constants_iterates_in_insertion_order() {
+        let mut chunk = Chunk::new();
+        chunk.emit_constant(Value::Number(1.0), 1);
+        chunk.emit_constant(Value::Str("two".to_string()), 1);
+
+        let constants: Vec<Value> = chunk.constants().cloned().collect();
+
+        assert_eq!(
+            constants,
+            vec![Value::Number(1.0), Value::Str("two".to_string())]
+        );
+    }
+
+    #[rstest]
+    fn test_disassemble_corrupted_code() {
+        let mut chunk = Chunk::new();
+        for byte in [255, OpCode::Constant.into(), 7, OpCode::Loop.into(), 0, 9] {
+            chunk.write(byte, 1);
+        }
+        chunk.write(OpCode::Jump.into(), 2);
+        chunk.write(0, 2);
+
+        let mut output = Vec::new();
+        chunk.disassemble("corrupted", &mut output);
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "==corrupted==\n\
+             0000    1 Unknown opcode 255\n\
+             0001    | OP_CONSTANT         1 <invalid constant 7>\n\
+             0003    | OP_LOOP             3 -> <before start>\n\
+             0006    2 OP_JUMP          <missing operand>\n"
+        );
+    }
+
+    #[rstest]
+    fn test_disassemble_loop_target() {
+        let mut chunk = Chunk::new();
+        chunk.write(OpCode::Nil.into(), 1);
+        for byte in [OpCode::Loop.into(), 0, 4] {
+            chunk.write(byte, 1);
+        }
+
+        let mut output = Vec::new();
+        chunk.disassemble_instruction(1, &mut output);
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "0001    | OP_LOOP             1 -> 0\n"
+        );
+    }
 }
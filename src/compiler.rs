@@ -1,12 +1,21 @@
-use crate::{function::*, opcode::OpCode, scanner::*, token::*, value::Value, InterpretResult};
+use crate::{
+    function::*,
+    opcode::{Completion, OpCode},
+    scanner::*,
+    token::*,
+    value::Value,
+    InterpretResult,
+};
 use std::cell::RefCell;
+use std::collections::HashSet;
 use std::io::Write;
 use std::mem;
 
 #[derive(Clone, Debug)]
 pub struct Local {
-    name: Token,
+    name: String,
     depth: Option<usize>,
+    is_const: bool,
 }
 
 #[derive(Default)]
@@ -15,15 +24,16 @@ pub struct Parser {
     current: Token,
     had_error: RefCell<bool>,
     panic_mode: RefCell<bool>,
+    errors: RefCell<Vec<String>>,
 }
 #[derive(Copy)]
-struct ParseRule {
+struct ParseRule<'a> {
     precedence: Precedence,
-    prefix: Option<fn(&mut Compiler, bool)>,
-    infix: Option<fn(&mut Compiler, bool)>,
+    prefix: Option<fn(&mut Compiler<'a>, bool)>,
+    infix: Option<fn(&mut Compiler<'a>, bool)>,
 }
-impl Default for ParseRule {
-    fn default() -> ParseRule {
+impl Default for ParseRule<'_> {
+    fn default() -> Self {
         Self {
             precedence: Precedence::None,
             prefix: None,
@@ -31,7 +41,7 @@ impl Default for ParseRule {
         }
     }
 }
-impl Clone for ParseRule {
+impl Clone for ParseRule<'_> {
     fn clone(&self) -> Self {
         *self
     }
@@ -40,13 +50,20 @@ impl Clone for ParseRule {
 enum Precedence {
     None = 0,
     Assignment,
+    Conditional,
     Or,
     And,
+    BitOr,
+    BitXor,
+    BitAnd,
     Equality,
     Comparison,
+    Range,
+    Shift,
     Term,
     Factor,
     Unary,
+    Exponent,
     Call,
     Primary,
 }
@@ -56,15 +73,22 @@ impl From<u8> for Precedence {
         match value {
             0 => Precedence::None,
             1 => Precedence::Assignment,
-            2 => Precedence::Or,
-            3 => Precedence::And,
-            4 => Precedence::Equality,
-            5 => Precedence::Comparison,
-            6 => Precedence::Term,
-            7 => Precedence::Factor,
-            8 => Precedence::Unary,
-            9 => Precedence::Call,
-            10 => Precedence::Primary,
+            2 => Precedence::Conditional,
+            3 => Precedence::Or,
+            4 => Precedence::And,
+            5 => Precedence::BitOr,
+            6 => Precedence::BitXor,
+            7 => Precedence::BitAnd,
+            8 => Precedence::Equality,
+            9 => Precedence::Comparison,
+            10 => Precedence::Range,
+            11 => Precedence::Shift,
+            12 => Precedence::Term,
+            13 => Precedence::Factor,
+            14 => Precedence::Unary,
+            15 => Precedence::Exponent,
+            16 => Precedence::Call,
+            17 => Precedence::Primary,
             _ => panic!("Should never happen!"),
         }
     }
@@ -79,39 +103,58 @@ impl Precedence {
         (precedence + 1).into()
     }
 }
-#[derive(Clone)]
+/// Whether the code being compiled is the top-level script or a function body.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FunctionType {
+    Function,
+    Script,
+}
+
 pub struct CompilationResult {
     pub function: Function,
+    pub function_type: FunctionType,
     pub scope_depth: usize,
     pub locals: Vec<Local>,
-    pub parent: Option<usize>,
 }
 
 impl Default for CompilationResult {
     fn default() -> Self {
+        Self::new("<script>", FunctionType::Script)
+    }
+}
+
+impl CompilationResult {
+    pub fn new(name: &str, function_type: FunctionType) -> Self {
         Self {
-            function: Function::new("<script>"),
+            function: Function::new(name),
+            function_type,
             scope_depth: 0,
             locals: Vec::new(),
-            parent: None,
         }
     }
-}
 
-impl CompilationResult {
     pub fn disassemble(&self, output: &mut impl Write) {
         self.function.disassemble(output);
     }
 }
 
-pub struct Compiler {
+pub struct Compiler<'a> {
     parser: Parser,
-    scanner: Scanner,
-    rules: Vec<ParseRule>,
+    scanner: Scanner<'a>,
+    rules: Vec<ParseRule<'a>>,
     result: CompilationResult,
+    /// Number of expressions being parsed. Inside one, a `//` following an operand
+    /// is integer division; anywhere else it starts a comment.
+    expression_depth: usize,
+    /// Results of the functions enclosing the one being compiled, innermost last.
+    enclosing: Vec<CompilationResult>,
+    /// Names of the global constants declared so far, which can't be assigned.
+    global_constants: HashSet<String>,
+    /// Index of the module whose code is compiled, recorded in every function.
+    module: usize,
 }
 
-impl Compiler {
+impl<'a> Compiler<'a> {
     pub fn new() -> Self {
         let mut rules = vec![
             ParseRule {
@@ -119,17 +162,18 @@ impl Compiler {
                 infix: None,
                 prefix: None
             };
-            40
+            TT::COUNT
         ];
         rules[TT::LeftParen as usize] = ParseRule {
-            precedence: Precedence::None,
+            precedence: Precedence::Call,
             prefix: Some(Compiler::grouping),
-            infix: None,
+            infix: Some(Compiler::call),
         };
+        rules[TT::Fun as usize].prefix = Some(Compiler::lambda);
         rules[TT::Minus as usize] = ParseRule {
             precedence: Precedence::Term,
             prefix: Some(Compiler::unary),
-            infix: Some(Compiler::unary),
+            infix: Some(Compiler::binary),
         };
         rules[TT::Plus as usize] = ParseRule {
             precedence: Precedence::Term,
@@ -149,6 +193,38 @@ impl Compiler {
             infix: Some(Compiler::binary),
         };
 
+        rules[TT::Percent as usize] = ParseRule {
+            precedence: Precedence::Factor,
+            prefix: None,
+            infix: Some(Compiler::binary),
+        };
+
+        rules[TT::SlashSlash as usize] = ParseRule {
+            precedence: Precedence::Factor,
+            prefix: None,
+            infix: Some(Compiler::binary),
+        };
+
+        rules[TT::StarStar as usize] = ParseRule {
+            precedence: Precedence::Exponent,
+            prefix: None,
+            infix: Some(Compiler::binary),
+        };
+
+        rules[TT::LeftBracket as usize] = ParseRule {
+            precedence: Precedence::Call,
+            prefix: Some(Compiler::list),
+            infix: Some(Compiler::index),
+        };
+
+        rules[TT::LeftBrace as usize].prefix = Some(Compiler::map);
+
+        rules[TT::Dot as usize] = ParseRule {
+            precedence: Precedence::Call,
+            prefix: None,
+            infix: Some(Compiler::property),
+        };
+
         rules[TT::Identifier as usize] = ParseRule {
             prefix: Some(Compiler::variable),
             precedence: Precedence::None,
@@ -159,7 +235,7 @@ impl Compiler {
             prefix: Some(Compiler::number),
             infix: None,
         };
-        rules[TT::Constant as usize] = ParseRule {
+        rules[TT::Literal as usize] = ParseRule {
             precedence: Precedence::None,
             prefix: Some(Compiler::literal),
             infix: None,
@@ -169,6 +245,11 @@ impl Compiler {
             prefix: Some(Compiler::string),
             infix: None,
         };
+        rules[TT::Interpolation as usize] = ParseRule {
+            precedence: Precedence::None,
+            prefix: Some(Compiler::interpolation),
+            infix: None,
+        };
         rules[TT::Bang as usize].prefix = Some(Compiler::unary);
 
         rules[TT::BangEquals as usize] = ParseRule {
@@ -186,6 +267,32 @@ impl Compiler {
         rules[TT::GreaterEquals as usize] = rules[TT::Greater as usize];
         rules[TT::Less as usize] = rules[TT::Greater as usize];
         rules[TT::LessEquals as usize] = rules[TT::Greater as usize];
+        rules[TT::In as usize] = rules[TT::Greater as usize];
+
+        for (ttype, precedence) in [
+            (TT::Ampersand, Precedence::BitAnd),
+            (TT::Pipe, Precedence::BitOr),
+            (TT::Caret, Precedence::BitXor),
+            (TT::LessLess, Precedence::Shift),
+            (TT::GreaterGreater, Precedence::Shift),
+            (TT::DotDot, Precedence::Range),
+            (TT::DotDotEquals, Precedence::Range),
+        ] {
+            rules[ttype as usize] = ParseRule {
+                precedence,
+                prefix: None,
+                infix: Some(Compiler::binary),
+            };
+        }
+        rules[TT::Tilde as usize].prefix = Some(Compiler::unary);
+
+        for ttype in [TT::PlusPlus, TT::MinusMinus] {
+            rules[ttype as usize] = ParseRule {
+                precedence: Precedence::Call,
+                prefix: Some(Compiler::prefix_increment),
+                infix: Some(Compiler::invalid_increment),
+            };
+        }
 
         rules[TT::And as usize] = ParseRule {
             prefix: None,
@@ -199,11 +306,29 @@ impl Compiler {
             infix: Some(Compiler::or),
         };
 
+        rules[TT::Question as usize] = ParseRule {
+            prefix: None,
+            precedence: Precedence::Conditional,
+            infix: Some(Compiler::conditional),
+        };
+
         Self {
             parser: Parser::default(),
             scanner: Scanner::new(""),
             rules,
             result: CompilationResult::default(),
+            expression_depth: 0,
+            enclosing: Vec::new(),
+            global_constants: HashSet::new(),
+            module: 0,
+        }
+    }
+
+    /// A compiler for the code of the module with index `module` in the VM.
+    pub fn for_module(module: usize) -> Self {
+        Self {
+            module,
+            ..Self::new()
         }
     }
 
@@ -211,8 +336,14 @@ impl Compiler {
         *self.parser.had_error.borrow()
     }
 
-    pub fn compile(&mut self, source: &str) -> Result<CompilationResult, InterpretResult> {
+    /// Diagnostics reported during the last compilation, one line per error.
+    pub fn errors(&self) -> Vec<String> {
+        self.parser.errors.borrow().clone()
+    }
+
+    pub fn compile(&mut self, source: &'a str) -> Result<CompilationResult, InterpretResult> {
         self.initialize();
+        self.result.function.module = self.module;
         self.scanner = Scanner::new(source);
         self.advance();
 
@@ -227,7 +358,7 @@ impl Compiler {
         if self.had_error() {
             Err(InterpretResult::CompilerError)
         } else {
-            Ok(self.result.clone())
+            Ok(mem::take(&mut self.result))
         }
     }
 
@@ -241,14 +372,17 @@ impl Compiler {
 
     fn advance(&mut self) {
         self.parser.previous = self.parser.current.clone();
+        self.scanner.allow_integer_division(
+            self.expression_depth > 0 && self.parser.previous.ttype.ends_operand(),
+        );
         loop {
             self.parser.current = self.scanner.scan_token();
 
             if self.parser.current.ttype != TT::Error {
                 break;
             }
-            let message = self.parser.current.lexeme.as_str();
-            self.error_at_current(message);
+            let message = self.parser.current.message().to_string();
+            self.error_at_current(&message);
         }
     }
 
@@ -257,6 +391,12 @@ impl Compiler {
             self.fun_declaration();
         } else if self.is_match(TT::Var) {
             self.var_declaration();
+        } else if self.is_match(TT::Const) {
+            self.const_declaration();
+        } else if self.is_match(TT::Import) {
+            self.import_declaration();
+        } else if self.is_match(TT::From) {
+            self.import_names_declaration();
         } else {
             self.statement();
         }
@@ -267,32 +407,170 @@ impl Compiler {
     fn fun_declaration(&mut self) {
         let global = self.parse_variable("Expected function name");
         self.mark_initialized();
-        self.function();
+        let name = self.lexeme(&self.parser.previous);
+        self.consume(TT::LeftParen, "Expect '(' after function name.");
+        self.function(name, Compiler::block_body);
         self.define_variable(global);
     }
 
-    fn function(&mut self) {
-        let old_result = mem::take(&mut self.result);
+    /// Compiles the anonymous function `fun (a, b) { ... }`.
+    fn lambda(&mut self, _can_assign: bool) {
+        self.consume(TT::LeftParen, "Expect '(' after 'fun'.");
+        self.function("<lambda>", Compiler::block_body);
+    }
+
+    /// Compiles the parameters following `(` and then the body of a function with
+    /// `body`, and emits the function as a constant of the enclosing code.
+    fn function(&mut self, name: &str, body: fn(&mut Compiler<'a>)) {
+        let enclosing = mem::replace(
+            &mut self.result,
+            CompilationResult::new(name, FunctionType::Function),
+        );
+        // The body is made of statements even when the function is an expression.
+        let expression_depth = mem::take(&mut self.expression_depth);
+        self.enclosing.push(enclosing);
+        self.result.function.module = self.module;
         self.begin_scope();
+        self.parameters();
+        body(self);
+        self.expression_depth = expression_depth;
+        let enclosing = self
+            .enclosing
+            .pop()
+            .expect("function has an enclosing result");
+        let compiled = mem::replace(&mut self.result, enclosing);
+        self.emit_constant(Value::Func(compiled.function.into()));
+    }
 
-        self.end_compiler();
+    /// Compiles `a, b = default, ...rest)`. Required parameters come first, then
+    /// parameters with a default value and finally an optional rest parameter.
+    fn parameters(&mut self) {
+        if !self.check(TT::RightParen) {
+            loop {
+                if self.result.function.variadic {
+                    self.error_at_current("Rest parameter must be the last parameter.");
+                }
+                if self.is_match(TT::DotDotDot) {
+                    self.rest_parameter();
+                } else {
+                    self.parameter();
+                }
+                if !self.is_match(TT::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TT::RightParen, "Expect ')' after parameters.");
+    }
+
+    /// Compiles a named parameter. A default value is evaluated in the function's
+    /// prologue, when the call didn't pass the argument.
+    fn parameter(&mut self) {
+        let function = &mut self.result.function;
+        if function.max_arity == u8::MAX {
+            self.error_at_current("Can't have more than 255 parameters.");
+            return;
+        }
+        let slot = function.max_arity;
+        function.max_arity += 1;
+        let index = self.parse_variable("Expect parameter name.");
+        if self.is_match(TT::Assign) {
+            self.emit_bytes(OpCode::JumpIfPassed, slot);
+            self.emit_byte(0xff);
+            self.emit_byte(0xff);
+            let skip_default = self.result.function.size() - 2;
+            self.expression();
+            self.emit_bytes(OpCode::SetLocal, slot);
+            self.emit_byte(OpCode::Pop.into());
+            self.finish_jump(skip_default);
+        } else if self.result.function.min_arity < slot {
+            self.error("Parameter without a default value can't follow one with a default.");
+        } else {
+            self.result.function.min_arity += 1;
+        }
+        self.define_variable(index);
+    }
 
-        self.result = mem::replace(&mut self.result, old_result.clone());
-        if !*self.parser.had_error.borrow() {
-            let func = self
-                .result
-                .function
-                .make_constant(Value::Func(old_result.function.clone()));
-            self.emit_bytes(OpCode::Constant, func)
+    fn rest_parameter(&mut self) {
+        let index = self.parse_variable("Expect parameter name after '...'.");
+        if self.is_match(TT::Assign) {
+            self.error("Rest parameter can't have a default value.");
+            self.expression();
+        }
+        self.result.function.variadic = true;
+        self.define_variable(index);
+    }
+
+    fn block_body(&mut self) {
+        self.consume(TT::LeftBrace, "Expect '{' before function body.");
+        self.block();
+        self.emit_return();
+    }
+
+    /// The body of `(a, b) => a + b`, a single expression whose value is returned.
+    fn arrow_body(&mut self) {
+        self.consume(TT::Arrow, "Expect '=>' after parameters.");
+        self.expression();
+        self.emit_byte(OpCode::Return.into());
+    }
+
+    /// Whether the `(` just consumed starts the parameter list of an arrow function:
+    /// whether its matching `)` is followed by `=>`. The tokens are scanned ahead on a
+    /// copy of the scanner.
+    fn arrow_follows(&self) -> bool {
+        let mut scanner = self.scanner.clone();
+        let mut ttype = self.parser.current.ttype;
+        let mut depth = 0;
+        loop {
+            match ttype {
+                TT::LeftParen => depth += 1,
+                TT::RightParen if depth == 0 => break,
+                TT::RightParen => depth -= 1,
+                TT::EndOfFile => return false,
+                _ => {}
+            }
+            ttype = scanner.scan_token().ttype;
+        }
+        scanner.scan_token().ttype == TT::Arrow
+    }
+
+    fn call(&mut self, _can_assign: bool) {
+        let mut count = 0;
+        if !self.check(TT::RightParen) {
+            loop {
+                self.expression();
+                if count == u8::MAX as usize {
+                    self.error("Can't have more than 255 arguments.");
+                }
+                count += 1;
+                if !self.is_match(TT::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TT::RightParen, "Expect ')' after arguments.");
+        self.emit_bytes(OpCode::Call, count.min(u8::MAX as usize) as u8);
+    }
+
+    fn return_statement(&mut self) {
+        if self.result.function_type == FunctionType::Script {
+            self.error("Can't return from top-level code.");
+        }
+        if self.is_match(TT::Semicolon) {
+            self.emit_return();
+        } else {
+            self.expression();
+            self.consume(TT::Semicolon, "Expect ';' after return value.");
+            self.emit_byte(OpCode::Return.into());
         }
     }
 
     fn block(&mut self) {
-        while !self.check(TT::RightBracket) && !self.check(TT::EndOfFile) {
+        while !self.check(TT::RightBrace) && !self.check(TT::EndOfFile) {
             self.declaration();
         }
 
-        self.consume(TT::RightBracket, "Expect '}' after block.");
+        self.consume(TT::RightBrace, "Expect '}' after block.");
     }
 
     fn begin_scope(&mut self) {
@@ -321,7 +599,13 @@ impl Compiler {
             self.while_statement();
         } else if self.is_match(TT::For) {
             self.for_statement();
-        } else if self.is_match(TT::LeftBracket) {
+        } else if self.is_match(TT::Return) {
+            self.return_statement();
+        } else if self.is_match(TT::Throw) {
+            self.throw_statement();
+        } else if self.is_match(TT::Try) {
+            self.try_statement();
+        } else if self.is_match(TT::LeftBrace) {
             self.begin_scope();
             self.block();
             self.end_scope();
@@ -332,7 +616,76 @@ impl Compiler {
 
     fn var_declaration(&mut self) {
         let global = self.parse_variable("Expect variable name.");
+        self.variable_initializer(global);
+    }
+
+    /// Compiles `const name = value;`, a variable that can't be assigned again.
+    fn const_declaration(&mut self) {
+        let global = self.parse_variable("Expect constant name.");
+        let name = self.lexeme(&self.parser.previous);
+        self.consume(TT::Assign, "Expect '=' after constant name.");
+        self.expression();
+        self.consume(TT::Semicolon, "Expect ';' after expression.");
+
+        if self.result.scope_depth == 0 {
+            self.global_constants.insert(name.to_string());
+            self.emit_bytes(OpCode::DefineConstant, global);
+        } else {
+            self.mark_initialized();
+            let index = self.result.locals.len() - 1;
+            self.result.locals[index].is_const = true;
+        }
+    }
 
+    /// Compiles `import "path" as name;`, which binds the module to `name`.
+    fn import_declaration(&mut self) {
+        let path = self.module_path("Expect module path after 'import'.");
+        self.consume(TT::As, "Expect 'as' after module path.");
+        let global = self.parse_variable("Expect module name after 'as'.");
+        self.emit_bytes(OpCode::Import, path);
+        self.consume(TT::Semicolon, "Expect ';' after import.");
+        self.define_variable(global);
+    }
+
+    /// Compiles `from "path" import a, b;`, which binds each name to the global of
+    /// the same name in the module.
+    fn import_names_declaration(&mut self) {
+        let path = self.module_path("Expect module path after 'from'.");
+        self.consume(TT::Import, "Expect 'import' after module path.");
+        loop {
+            let global = self.parse_variable("Expect name to import.");
+            let name = self.identifier_constant(self.lexeme(&self.parser.previous));
+            self.emit_bytes(OpCode::Import, path);
+            self.emit_bytes(OpCode::GetProperty, name);
+            self.define_variable(global);
+            if !self.is_match(TT::Comma) {
+                break;
+            }
+        }
+        self.consume(TT::Semicolon, "Expect ';' after imported names.");
+    }
+
+    /// Consumes the string naming the module of an import, returning its constant.
+    fn module_path(&mut self, error_message: &str) -> u8 {
+        self.consume(TT::String, error_message);
+        match &self.parser.previous.literal {
+            Some(Literal::String(path)) => {
+                let path = Value::Str(path.clone());
+                self.result.function.make_constant(path)
+            }
+            _ => 0,
+        }
+    }
+
+    /// Compiles `module.name`, which reads a global of an imported module.
+    fn property(&mut self, _can_assign: bool) {
+        self.consume(TT::Identifier, "Expect name after '.'.");
+        let name = self.identifier_constant(self.lexeme(&self.parser.previous));
+        self.emit_bytes(OpCode::GetProperty, name);
+    }
+
+    /// Compiles the rest of `var name = value;` once the name has been declared.
+    fn variable_initializer(&mut self, global: u8) {
         if self.is_match(TT::Assign) {
             self.expression();
         } else {
@@ -349,8 +702,11 @@ impl Compiler {
 
         self.declare_variable();
         if self.result.scope_depth == 0 {
-            let name = self.parser.previous.lexeme.clone();
-            self.identifier_constant(&name)
+            let name = self.lexeme(&self.parser.previous);
+            if self.global_constants.contains(name) {
+                self.error("Already declared constant with this name.");
+            }
+            self.identifier_constant(name)
         } else {
             0
         }
@@ -358,60 +714,155 @@ impl Compiler {
 
     fn declare_variable(&mut self) {
         if self.result.scope_depth != 0 {
-            let name = self.parser.previous.lexeme.clone();
+            let name = self.lexeme(&self.parser.previous);
             for x in self.result.locals.iter().rev() {
                 if x.depth.unwrap() < self.result.scope_depth {
                     break;
                 }
-                if x.name.lexeme == name {
+                if x.name == name {
                     self.error("Already declared variable with this name in the current scope.");
                     return;
                 }
             }
 
-            let token = self.parser.previous.clone();
-            self.add_local(token);
+            self.add_local(name);
         }
     }
 
-    fn add_local(&mut self, token: Token) {
+    fn add_local(&mut self, name: &str) {
         if self.result.locals.len() >= 256 {
             self.error("Too many local variables in the scope!");
             return;
         }
         let local = Local {
-            name: token,
+            name: name.to_string(),
             depth: None,
+            is_const: false,
         };
         self.result.locals.push(local);
     }
 
     fn variable(&mut self, can_assign: bool) {
-        self.named_variable(&self.parser.previous.lexeme.clone(), can_assign);
+        self.named_variable(self.lexeme(&self.parser.previous), can_assign);
     }
 
     fn named_variable(&mut self, name: &str, can_assign: bool) {
-        let (index, get_op, set_op) = if let Some(local_arg) = self.resolve_local(name) {
+        let target = self.parser.previous.clone();
+        let (index, get_op, set_op) = self.resolve_variable(name);
+
+        if can_assign && self.is_match(TT::Assign) {
+            self.check_assignable(&target);
+            self.expression();
+            self.emit_bytes(set_op, index);
+        } else if let Some(operator) = self.compound_assignment(can_assign) {
+            self.check_assignable(&target);
+            self.emit_bytes(get_op, index);
+            self.expression();
+            self.emit_byte(operator.into());
+            self.emit_bytes(set_op, index);
+        } else if self.is_match(TT::PlusPlus) || self.is_match(TT::MinusMinus) {
+            // Postfix: the first read is the value of the expression, the second one
+            // is incremented and stored.
+            let operator = self.increment_operator();
+            self.check_assignable(&target);
+            self.emit_bytes(get_op.clone(), index);
+            self.emit_bytes(get_op, index);
+            self.emit_constant(Value::Number(1.0));
+            self.emit_byte(operator.into());
+            self.emit_bytes(set_op, index);
+            self.emit_byte(OpCode::Pop.into());
+        } else {
+            self.emit_bytes(get_op, index)
+        }
+    }
+
+    /// The operand and the instructions reading and writing the variable `name`.
+    fn resolve_variable(&mut self, name: &str) -> (u8, OpCode, OpCode) {
+        if let Some(local_arg) = self.resolve_local(name) {
             (local_arg, OpCode::GetLocal, OpCode::SetLocal)
         } else {
+            if self.is_enclosing_local(name) {
+                self.error(&format!(
+                    "Can't capture local variable '{name}' of an enclosing function."
+                ));
+            }
             (
                 self.identifier_constant(name),
                 OpCode::GetGlobal,
                 OpCode::SetGlobal,
             )
+        }
+    }
+
+    /// Reports an error if the variable named by `target` is a constant.
+    fn check_assignable(&self, target: &Token) {
+        let name = self.lexeme(target);
+        let is_const = match self
+            .result
+            .locals
+            .iter()
+            .rev()
+            .find(|local| local.name == name)
+        {
+            Some(local) => local.is_const,
+            None => self.global_constants.contains(name),
         };
+        if is_const {
+            self.error_at(target, "Can't assign to a constant.");
+        }
+    }
 
-        if can_assign && self.is_match(TT::Assign) {
-            self.expression();
-            self.emit_bytes(set_op, index);
+    /// Consumes a compound assignment operator such as `+=`, returning the operation
+    /// it applies.
+    fn compound_assignment(&mut self, can_assign: bool) -> Option<OpCode> {
+        if !can_assign {
+            return None;
+        }
+        let operator = match self.parser.current.ttype {
+            TT::PlusAssign => OpCode::Add,
+            TT::MinusAssign => OpCode::Substract,
+            TT::StarAssign => OpCode::Multiply,
+            TT::SlashAssign => OpCode::Divide,
+            TT::PercentAssign => OpCode::Modulo,
+            _ => return None,
+        };
+        self.advance();
+        Some(operator)
+    }
+
+    /// The operation applied by the `++` or `--` just consumed.
+    fn increment_operator(&self) -> OpCode {
+        if self.parser.previous.ttype == TT::PlusPlus {
+            OpCode::Add
         } else {
-            self.emit_bytes(get_op, index)
+            OpCode::Substract
         }
     }
 
+    /// Compiles `++x` and `--x`, which evaluate to the updated value.
+    fn prefix_increment(&mut self, _can_assign: bool) {
+        let operator = self.increment_operator();
+        self.consume(
+            TT::Identifier,
+            "Expect variable name after increment operator.",
+        );
+        self.check_assignable(&self.parser.previous.clone());
+        let (index, get_op, set_op) = self.resolve_variable(self.lexeme(&self.parser.previous));
+        self.emit_bytes(get_op, index);
+        self.emit_constant(Value::Number(1.0));
+        self.emit_byte(operator.into());
+        self.emit_bytes(set_op, index);
+    }
+
+    /// A postfix `++` or `--` not consumed by `named_variable` follows something that
+    /// is not a variable.
+    fn invalid_increment(&mut self, _can_assign: bool) {
+        self.error("Invalid increment target.");
+    }
+
     fn resolve_local(&mut self, name: &str) -> Option<u8> {
         for (index, value) in self.result.locals.iter().rev().enumerate() {
-            if value.name.lexeme == name {
+            if value.name == name {
                 if value.depth.is_none() {
                     self.error("Cannot read local variable in its own initializer.")
                 }
@@ -421,6 +872,14 @@ impl Compiler {
         None
     }
 
+    /// Whether `name` is a local variable of a function enclosing the current one.
+    /// Functions can't capture them yet, and looking them up as globals would fail.
+    fn is_enclosing_local(&self, name: &str) -> bool {
+        self.enclosing
+            .iter()
+            .any(|result| result.locals.iter().any(|local| local.name == name))
+    }
+
     fn identifier_constant(&mut self, name: &str) -> u8 {
         self.result
             .function
@@ -491,7 +950,15 @@ impl Compiler {
         self.consume(TT::LeftParen, "Expect '(' after 'for'");
         if self.is_match(TT::Semicolon) {
         } else if self.is_match(TT::Var) {
-            self.var_declaration();
+            self.consume(TT::Identifier, "Expect variable name.");
+            let name = self.parser.previous.clone();
+            if self.is_match(TT::In) {
+                self.for_in_statement(&name);
+                self.end_scope();
+                return;
+            }
+            self.declare_variable();
+            self.variable_initializer(0);
         } else {
             self.expression_statement();
         }
@@ -529,6 +996,186 @@ impl Compiler {
         self.end_scope();
     }
 
+    /// Compiles `for (var name in iterable) body` once `in` has been consumed. The
+    /// iterable and the loop's cursor live in two hidden locals, named so that no
+    /// identifier can refer to them, and `OP_FOR_ITER` pushes the next element as
+    /// the loop variable or leaves the loop.
+    fn for_in_statement(&mut self, name: &Token) {
+        let name = self.lexeme(name);
+        self.expression();
+        self.consume(TT::RightParen, "Expect ')' after for clauses.");
+        let slot = self.result.locals.len() as u8;
+        self.add_local("for iterable");
+        self.mark_initialized();
+        self.emit_constant(Value::Number(0.0));
+        self.add_local("for cursor");
+        self.mark_initialized();
+
+        let loop_start = self.result.function.size();
+        self.emit_bytes(OpCode::ForIter, slot);
+        self.emit_byte(0xff);
+        self.emit_byte(0xff);
+        let exit_jump = self.result.function.size() - 2;
+
+        self.begin_scope();
+        self.add_local(name);
+        self.mark_initialized();
+        self.statement();
+        self.end_scope();
+        self.emit_loop(loop_start);
+
+        self.finish_jump(exit_jump);
+    }
+
+    fn throw_statement(&mut self) {
+        self.expression();
+        self.consume(TT::Semicolon, "Expect ';' after thrown value.");
+        self.emit_byte(OpCode::Throw.into());
+    }
+
+    /// Compiles `try { ... } catch (e) { ... } finally { ... }`, where either the
+    /// `catch` or the `finally` clause may be left out.
+    ///
+    /// The `try` block runs under a handler catching into the `catch` block. With a
+    /// `finally` block, the `catch` block runs under a second handler, and every way
+    /// out of them reaches the `finally` block with the value thrown or returned and
+    /// a `Completion` on the stack, which `OP_END_FINALLY` acts upon.
+    fn try_statement(&mut self) {
+        let try_handler = self.emit_handler(0);
+        self.consume(TT::LeftBrace, "Expect '{' after 'try'.");
+        self.scoped_block();
+        self.emit_byte(OpCode::PopHandler.into());
+
+        let has_finally =
+            self.check(TT::Finally) || (self.check(TT::Catch) && self.check_finally_after_catch());
+        if !has_finally && !self.check(TT::Catch) {
+            self.error_at_current("Expect 'catch' or 'finally' after try block.");
+        }
+        let mut to_finally = Vec::new();
+        if has_finally {
+            self.emit_completion(Completion::Normal);
+        }
+        to_finally.push(self.emit_jump(OpCode::Jump));
+        self.patch_handler(try_handler);
+
+        // Where a value thrown without a matching `catch` clause enters `finally`.
+        let mut rethrow = None;
+        if self.is_match(TT::Catch) {
+            self.consume(TT::LeftParen, "Expect '(' after 'catch'.");
+            self.consume(TT::Identifier, "Expect exception variable name.");
+            self.begin_scope();
+            self.add_local(self.lexeme(&self.parser.previous));
+            self.mark_initialized();
+            self.consume(TT::RightParen, "Expect ')' after exception variable.");
+            let catch_handler = has_finally.then(|| self.emit_handler(1));
+            self.consume(TT::LeftBrace, "Expect '{' before catch body.");
+            self.scoped_block();
+            if let Some(catch_handler) = catch_handler {
+                self.emit_byte(OpCode::PopHandler.into());
+                self.end_scope();
+                self.emit_completion(Completion::Normal);
+                to_finally.push(self.emit_jump(OpCode::Jump));
+                rethrow = Some(catch_handler);
+            } else {
+                self.end_scope();
+            }
+        } else {
+            rethrow = Some(try_handler);
+        }
+
+        if !self.is_match(TT::Finally) {
+            for jump in to_finally {
+                self.finish_jump(jump);
+            }
+            return;
+        }
+        if let Some(handler) = rethrow {
+            if handler != try_handler {
+                self.patch_handler(handler);
+            }
+            self.emit_constant(Completion::Throw.into());
+        }
+        let finally_start = self.result.function.size();
+        for jump in to_finally {
+            self.finish_jump(jump);
+        }
+        for handler in [Some(try_handler), rethrow].into_iter().flatten() {
+            self.patch_finally(handler, finally_start);
+        }
+
+        // The thrown or returned value and the completion are hidden locals.
+        self.begin_scope();
+        self.add_local("finally value");
+        self.mark_initialized();
+        self.add_local("finally completion");
+        self.mark_initialized();
+        self.consume(TT::LeftBrace, "Expect '{' after 'finally'.");
+        self.scoped_block();
+        self.result.scope_depth -= 1;
+        self.result.locals.truncate(self.result.locals.len() - 2);
+        self.emit_byte(OpCode::EndFinally.into());
+    }
+
+    /// Whether the `catch` clause at the current token is followed by a `finally`
+    /// clause, found by skipping its block on a copy of the scanner.
+    fn check_finally_after_catch(&self) -> bool {
+        let mut scanner = self.scanner.clone();
+        let mut ttype = self.parser.current.ttype;
+        let mut depth = 0;
+        loop {
+            match ttype {
+                TT::LeftBrace => depth += 1,
+                TT::RightBrace if depth <= 1 => break,
+                TT::RightBrace => depth -= 1,
+                TT::EndOfFile => return false,
+                _ => {}
+            }
+            ttype = scanner.scan_token().ttype;
+        }
+        scanner.scan_token().ttype == TT::Finally
+    }
+
+    fn scoped_block(&mut self) {
+        self.begin_scope();
+        self.block();
+        self.end_scope();
+    }
+
+    fn emit_completion(&mut self, completion: Completion) {
+        self.emit_byte(OpCode::Nil.into());
+        self.emit_constant(completion.into());
+    }
+
+    /// Emits `OP_PUSH_HANDLER` discarding the `discard` innermost locals, returning
+    /// its offset for `patch_handler` and `patch_finally`.
+    fn emit_handler(&mut self, discard: u8) -> usize {
+        self.emit_bytes(OpCode::PushHandler, discard);
+        for _ in 0..4 {
+            self.emit_byte(0);
+        }
+        self.result.function.size() - 6
+    }
+
+    /// Makes the handler at `handler` catch at the current offset.
+    fn patch_handler(&mut self, handler: usize) {
+        let target = self.result.function.size();
+        self.patch_handler_target(handler, 2, target);
+    }
+
+    fn patch_finally(&mut self, handler: usize, target: usize) {
+        self.patch_handler_target(handler, 4, target);
+    }
+
+    fn patch_handler_target(&mut self, handler: usize, operand: usize, target: usize) {
+        let jump = target - (handler + 6);
+        if jump > u16::MAX as usize {
+            self.error("Too much code to jump over.");
+        }
+        let function = &mut self.result.function;
+        function.write_at(handler + operand, ((jump >> 8) & 0xff) as u8);
+        function.write_at(handler + operand + 1, (jump & 0xff) as u8);
+    }
+
     fn emit_loop(&mut self, loop_start: usize) {
         self.emit_byte(OpCode::Loop.into());
         let offset: usize = self.result.function.size() - loop_start + 2;
@@ -579,6 +1226,24 @@ impl Compiler {
         self.finish_jump(end_jump);
     }
 
+    /// Compiles `cond ? a : b`; only the selected branch is evaluated. The else branch
+    /// is parsed at `Conditional` precedence, making the operator right-associative.
+    fn conditional(&mut self, _can_assign: bool) {
+        let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_byte(OpCode::Pop.into());
+        self.expression();
+        self.consume(
+            TT::Colon,
+            "Expect ':' after then branch of conditional expression.",
+        );
+
+        let end_jump = self.emit_jump(OpCode::Jump);
+        self.finish_jump(else_jump);
+        self.emit_byte(OpCode::Pop.into());
+        self.parse_precendence(Precedence::Conditional);
+        self.finish_jump(end_jump);
+    }
+
     fn expression_statement(&mut self) {
         self.expression();
         self.consume(TT::Semicolon, "Expected ';' after expression.");
@@ -595,11 +1260,16 @@ impl Compiler {
                 TT::Class
                     | TT::Fun
                     | TT::Var
+                    | TT::Const
+                    | TT::Import
+                    | TT::From
                     | TT::For
                     | TT::If
                     | TT::While
                     | TT::Print
                     | TT::Return
+                    | TT::Throw
+                    | TT::Try
             ) {
                 return;
             }
@@ -607,6 +1277,23 @@ impl Compiler {
             self.advance();
         }
     }
+    /// Consumes `=` or a compound assignment operator.
+    fn match_assignment(&mut self) -> bool {
+        let assignment = matches!(
+            self.parser.current.ttype,
+            TT::Assign
+                | TT::PlusAssign
+                | TT::MinusAssign
+                | TT::StarAssign
+                | TT::SlashAssign
+                | TT::PercentAssign
+        );
+        if assignment {
+            self.advance();
+        }
+        assignment
+    }
+
     fn check(&self, ttype: TT) -> bool {
         self.parser.current.ttype == ttype
     }
@@ -620,6 +1307,10 @@ impl Compiler {
         }
     }
 
+    fn lexeme(&self, token: &Token) -> &'a str {
+        self.scanner.lexeme(token)
+    }
+
     fn error_at_current(&self, message: &str) {
         let current: &Token = &self.parser.current.clone();
         self.error_at(current, message)
@@ -635,17 +1326,15 @@ impl Compiler {
         }
         self.parser.panic_mode.replace(true);
 
-        eprint!("[line {}] Error", token.line);
-        match token.ttype {
-            TT::EndOfFile => {
-                eprint!(" at end");
-            }
-            TT::Error => {}
-            _ => {
-                eprint!(" at {}", token.lexeme)
-            }
-        }
-        eprintln!(" : {}", message);
+        let location = match token.ttype {
+            TT::EndOfFile => " at end".to_string(),
+            TT::Error => "".to_string(),
+            _ => format!(" at '{}'", self.lexeme(token)),
+        };
+        self.parser.errors.borrow_mut().push(format!(
+            "[line {}] Error{}: {}",
+            token.line, location, message
+        ));
         self.parser.had_error.replace(true);
     }
 
@@ -685,22 +1374,56 @@ impl Compiler {
         }
     }
 
+    /// Compiles `"a ${x} b ${y} c"` into `"a " + str(x) + " b " + str(y) + " c"`.
+    fn interpolation(&mut self, _can_assign: bool) {
+        self.string(false);
+        loop {
+            self.expression();
+            self.emit_byte(OpCode::Stringify.into());
+            self.emit_byte(OpCode::Add.into());
+            if !self.is_match(TT::Interpolation) {
+                break;
+            }
+            self.string(false);
+            self.emit_byte(OpCode::Add.into());
+        }
+        self.consume(TT::String, "Expect end of string after interpolation.");
+        self.string(false);
+        self.emit_byte(OpCode::Add.into());
+    }
+
     fn binary(&mut self, _can_assign: bool) {
         let op_type = self.parser.previous.ttype;
         let rule = &self.rules[op_type as usize];
-        self.parse_precendence(rule.precedence.next());
+        if op_type == TT::StarStar {
+            // Right-associative: 2 ** 3 ** 2 is 2 ** (3 ** 2).
+            self.parse_precendence(rule.precedence);
+        } else {
+            self.parse_precendence(rule.precedence.next());
+        }
 
         match op_type {
             TT::Plus => self.emit_byte(OpCode::Add.into()),
             TT::Minus => self.emit_byte(OpCode::Substract.into()),
             TT::Star => self.emit_byte(OpCode::Multiply.into()),
             TT::Slash => self.emit_byte(OpCode::Divide.into()),
+            TT::Percent => self.emit_byte(OpCode::Modulo.into()),
+            TT::SlashSlash => self.emit_byte(OpCode::IntDivide.into()),
+            TT::StarStar => self.emit_byte(OpCode::Power.into()),
+            TT::Ampersand => self.emit_byte(OpCode::BitAnd.into()),
+            TT::Pipe => self.emit_byte(OpCode::BitOr.into()),
+            TT::Caret => self.emit_byte(OpCode::BitXor.into()),
+            TT::LessLess => self.emit_byte(OpCode::ShiftLeft.into()),
+            TT::GreaterGreater => self.emit_byte(OpCode::ShiftRight.into()),
             TT::Equals => self.emit_byte(OpCode::Equal.into()),
             TT::Less => self.emit_byte(OpCode::Less.into()),
             TT::Greater => self.emit_byte(OpCode::Greater.into()),
-            TT::BangEquals => self.emit_bytes(OpCode::Equal, OpCode::Not.into()),
-            TT::GreaterEquals => self.emit_bytes(OpCode::Greater, OpCode::Not.into()),
-            TT::LessEquals => self.emit_bytes(OpCode::Less, OpCode::Not.into()),
+            TT::BangEquals => self.emit_byte(OpCode::NotEqual.into()),
+            TT::GreaterEquals => self.emit_byte(OpCode::GreaterEqual.into()),
+            TT::LessEquals => self.emit_byte(OpCode::LessEqual.into()),
+            TT::In => self.emit_byte(OpCode::In.into()),
+            TT::DotDot => self.emit_byte(OpCode::Range.into()),
+            TT::DotDotEquals => self.emit_byte(OpCode::RangeInclusive.into()),
 
             _ => unreachable!("Should not be here!"),
         }
@@ -713,11 +1436,13 @@ impl Compiler {
         match operator {
             TT::Minus => self.emit_byte(OpCode::Negate.into()),
             TT::Bang => self.emit_byte(OpCode::Not.into()),
+            TT::Tilde => self.emit_byte(OpCode::BitNot.into()),
             _ => unreachable!("Should not happen!"),
         }
     }
 
     fn parse_precendence(&mut self, precedence: Precedence) {
+        self.expression_depth += 1;
         self.advance();
         if let Some(prefix_rule) = &self.rules[self.parser.previous.ttype as usize].prefix {
             let can_assign: bool = precedence <= Precedence::Assignment;
@@ -728,21 +1453,76 @@ impl Compiler {
                 if let Some(infix_rule) = &self.rules[self.parser.previous.ttype as usize].infix {
                     infix_rule(self, can_assign);
                 }
+            }
 
-                if can_assign && self.is_match(TT::Assign) {
-                    self.error("Invalid assignment target.");
-                }
+            if can_assign && self.match_assignment() {
+                self.error("Invalid assignment target.");
             }
         } else {
             self.error("Expected expression");
         }
+        self.expression_depth -= 1;
     }
 
     fn expression(&mut self) {
         self.parse_precendence(Precedence::Assignment)
     }
 
+    /// Compiles a list literal `[a, b, c]`, which may end with a trailing comma.
+    fn list(&mut self, _can_assign: bool) {
+        let mut count = 0;
+        while !self.check(TT::RightBracket) && !self.check(TT::EndOfFile) {
+            self.expression();
+            if count == u8::MAX as usize {
+                self.error("Can't have more than 255 elements in a list literal.");
+            }
+            count += 1;
+            if !self.is_match(TT::Comma) {
+                break;
+            }
+        }
+        self.consume(TT::RightBracket, "Expect ']' after list elements.");
+        self.emit_bytes(OpCode::BuildList, count.min(u8::MAX as usize) as u8);
+    }
+
+    /// Compiles a map literal `{key: value, ...}`, which may end with a trailing comma.
+    /// At the start of a statement `{` opens a block instead.
+    fn map(&mut self, _can_assign: bool) {
+        let mut count = 0;
+        while !self.check(TT::RightBrace) && !self.check(TT::EndOfFile) {
+            self.expression();
+            self.consume(TT::Colon, "Expect ':' after map key.");
+            self.expression();
+            if count == u8::MAX as usize {
+                self.error("Can't have more than 255 entries in a map literal.");
+            }
+            count += 1;
+            if !self.is_match(TT::Comma) {
+                break;
+            }
+        }
+        self.consume(TT::RightBrace, "Expect '}' after map entries.");
+        self.emit_bytes(OpCode::BuildMap, count.min(u8::MAX as usize) as u8);
+    }
+
+    /// Compiles `target[index]` and the assignment `target[index] = value`.
+    fn index(&mut self, can_assign: bool) {
+        self.expression();
+        self.consume(TT::RightBracket, "Expect ']' after index.");
+
+        if can_assign && self.is_match(TT::Assign) {
+            self.expression();
+            self.emit_byte(OpCode::IndexSet.into());
+        } else {
+            self.emit_byte(OpCode::IndexGet.into());
+        }
+    }
+
     fn grouping(&mut self, _can_assign: bool) {
+        if self.arrow_follows() {
+            self.function("<lambda>", Compiler::arrow_body);
+            return;
+        }
         self.expression();
         self.consume(TT::RightParen, "Expected ')' after expression.")
     }
@@ -773,7 +1553,11 @@ impl Compiler {
         self.emit_byte(byte2);
     }
 
+    /// Returns from the script, or returns `nil` from a function.
     fn emit_return(&mut self) {
+        if self.result.function_type == FunctionType::Function {
+            self.emit_byte(OpCode::Nil.into());
+        }
         self.emit_byte(OpCode::Return.into())
     }
 
@@ -781,3 +1565,487 @@ impl Compiler {
         self.emit_return()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use std::rc::Rc;
+
+    fn disassemble(source: &str) -> String {
+        let mut compiler = Compiler::new();
+        let compiled = compiler.compile(source).expect("source should compile");
+        let mut output = Vec::new();
+        compiled.disassemble(&mut output);
+        String::from_utf8(output).unwrap()
+    }
+
+    /// The first function declared by `source`.
+    fn compile_function(source: &str) -> Rc<Function> {
+        let mut compiler = Compiler::new();
+        let compiled = compiler.compile(source).expect("source should compile");
+        let chunk = compiled.function.chunk.borrow();
+        let function = chunk
+            .constants()
+            .find_map(|constant| match constant {
+                Value::Func(function) => Some(function.clone()),
+                _ => None,
+            })
+            .expect("source should declare a function");
+        function
+    }
+
+    fn compile_errors(source: &str) -> Vec<String> {
+        let mut compiler = Compiler::new();
+        assert!(compiler.compile(source).is_err());
+        compiler.errors()
+    }
+
+    #[rstest]
+    fn test_binary_minus_bytecode() {
+        assert_eq!(
+            disassemble("print 3 - 1;"),
+            "==<script>==\n\
+             0000    1 OP_CONSTANT         0 '3'\n\
+             0002    | OP_CONSTANT         2 '1'\n\
+             0004    | OP_SUBSTRACT\n\
+             0005    | OP_PRINT\n\
+             0006    | OP_RETURN\n"
+        );
+    }
+
+    #[rstest]
+    fn test_conditional_bytecode() {
+        assert_eq!(
+            disassemble("print true ? 1 : 2;"),
+            "==<script>==\n\
+             0000    1 OP_TRUE\n\
+             0001    | OP_JUMP_IF_FALSE    1 -> 10\n\
+             0004    | OP_POP\n\
+             0005    | OP_CONSTANT         5 '1'\n\
+             0007    | OP_JUMP             7 -> 13\n\
+             0010    | OP_POP\n\
+             0011    | OP_CONSTANT        11 '2'\n\
+             0013    | OP_PRINT\n\
+             0014    | OP_RETURN\n"
+        );
+    }
+
+    #[rstest]
+    fn test_conditional_is_right_associative() {
+        // a ? 1 : (b ? 2 : 3): the second condition is compiled inside the else branch.
+        assert_eq!(
+            disassemble("a ? 1 : b ? 2 : 3;"),
+            "==<script>==\n\
+             0000    1 OP_GET_GLOBAL       0 'a'\n\
+             0002    | OP_JUMP_IF_FALSE    2 -> 11\n\
+             0005    | OP_POP\n\
+             0006    | OP_CONSTANT         6 '1'\n\
+             0008    | OP_JUMP             8 -> 26\n\
+             0011    | OP_POP\n\
+             0012    | OP_GET_GLOBAL      12 'b'\n\
+             0014    | OP_JUMP_IF_FALSE   14 -> 23\n\
+             0017    | OP_POP\n\
+             0018    | OP_CONSTANT        18 '2'\n\
+             0020    | OP_JUMP            20 -> 26\n\
+             0023    | OP_POP\n\
+             0024    | OP_CONSTANT        24 '3'\n\
+             0026    | OP_POP\n\
+             0027    | OP_RETURN\n"
+        );
+    }
+
+    #[rstest]
+    fn test_conditional_binds_looser_than_or() {
+        // (a or b) ? 1 : 2
+        let bytecode = disassemble("a or b ? 1 : 2;");
+        let or_end = bytecode.find("'b'").unwrap();
+        let conditional = bytecode.find("'1'").unwrap();
+
+        assert!(or_end < conditional);
+    }
+
+    #[rstest]
+    #[case(
+        "true ? 1;",
+        "[line 1] Error at ';': Expect ':' after then branch of conditional expression."
+    )]
+    #[case(
+        "var a; true ? 1 : a = 2;",
+        "[line 1] Error at '=': Invalid assignment target."
+    )]
+    fn test_conditional_errors(#[case] source: &str, #[case] error: &str) {
+        assert_eq!(compile_errors(source), vec![error]);
+    }
+
+    #[rstest]
+    #[case("print 7 // 2;", true)]
+    #[case("print (a) // 2;", true)]
+    #[case("print xs[0] // 2;", true)]
+    #[case("var xs = []; // comment", false)]
+    #[case("print a\n  // 2\n;", false)]
+    #[case("print a; // 2", false)]
+    #[case("var a = 1; if (a == 1) // comment\n print a;", false)]
+    #[case("while (a < 3) // loop\n a = a + 1;", false)]
+    #[case("print 7 /// doc\n;", false)]
+    #[case("fun f() // doc\n{ return 1; }", false)]
+    #[case("var f = fun () { if (true) // comment\n print 1; };", false)]
+    fn test_slash_slash_divides_only_after_an_operand(#[case] source: &str, #[case] divides: bool) {
+        assert_eq!(disassemble(source).contains("OP_INT_DIVIDE"), divides);
+    }
+
+    #[rstest]
+    #[case("1 = a;", "[line 1] Error at '=': Invalid assignment target.")]
+    #[case("1 += a;", "[line 1] Error at '+=': Invalid assignment target.")]
+    #[case("a + b -= 1;", "[line 1] Error at '-=': Invalid assignment target.")]
+    #[case(
+        "++1;",
+        "[line 1] Error at '1': Expect variable name after increment operator."
+    )]
+    #[case("(a)++;", "[line 1] Error at '++': Invalid increment target.")]
+    #[case("a++ ++;", "[line 1] Error at '++': Invalid increment target.")]
+    fn test_assignment_target_errors(#[case] source: &str, #[case] error: &str) {
+        assert_eq!(compile_errors(source), vec![error]);
+    }
+
+    #[rstest]
+    fn test_for_in_bytecode() {
+        // The iterable and the cursor take slots 0 and 1, the loop variable slot 2.
+        assert_eq!(
+            disassemble("for (var c in s) print c;"),
+            "==<script>==\n\
+             0000    1 OP_GET_GLOBAL       0 's'\n\
+             0002    | OP_CONSTANT         2 '0'\n\
+             0004    | OP_FOR_ITER         0 -> 15\n\
+             0008    | OP_GET_LOCAL        2\n\
+             0010    | OP_PRINT\n\
+             0011    | OP_POP\n\
+             0012    | OP_LOOP            12 -> 4\n\
+             0015    | OP_POP\n\
+             0016    | OP_POP\n\
+             0017    | OP_RETURN\n"
+        );
+    }
+
+    #[rstest]
+    #[case(
+        "for (var x in) print x;",
+        "[line 1] Error at ')': Expected expression"
+    )]
+    #[case(
+        "for (var x in [1] print x;",
+        "[line 1] Error at 'print': Expect ')' after for clauses."
+    )]
+    #[case(
+        "for (var 1 in [1]) print 1;",
+        "[line 1] Error at '1': Expect variable name."
+    )]
+    fn test_for_in_errors(#[case] source: &str, #[case] error: &str) {
+        assert_eq!(compile_errors(source), vec![error]);
+    }
+
+    #[rstest]
+    fn test_arrow_function_bytecode() {
+        // Parameters are the first locals of the function and the body's value is
+        // returned directly.
+        assert_eq!(
+            disassemble("var f = (a, b) => a + b;"),
+            "==<script>==\n\
+             0000    1 OP_CONSTANT         0 'fn <lambda>'\n\
+             0002    | OP_DEFINE_GLOBAL    2 'f'\n\
+             0004    | OP_RETURN\n"
+        );
+        let lambda = compile_function("var f = (a, b) => a + b;");
+        let mut output = Vec::new();
+        lambda.disassemble(&mut output);
+
+        assert_eq!((lambda.min_arity, lambda.max_arity), (2, 2));
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "==<lambda>==\n\
+             0000    1 OP_GET_LOCAL        0\n\
+             0002    | OP_GET_LOCAL        1\n\
+             0004    | OP_ADD\n\
+             0005    | OP_RETURN\n"
+        );
+    }
+
+    #[rstest]
+    fn test_default_value_bytecode() {
+        // The default is only evaluated when the call didn't pass `b`.
+        let function = compile_function("fun f(a, b = 2) { return b; }");
+        let mut output = Vec::new();
+        function.disassemble(&mut output);
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "==f==\n\
+             0000    1 OP_JUMP_PASSED      1 -> 9\n\
+             0004    | OP_CONSTANT         4 '2'\n\
+             0006    | OP_SET_LOCAL        1\n\
+             0008    | OP_POP\n\
+             0009    | OP_GET_LOCAL        1\n\
+             0011    | OP_RETURN\n\
+             0012    | OP_NIL\n\
+             0013    | OP_RETURN\n"
+        );
+    }
+
+    #[rstest]
+    #[case("fun f() {}", (0, 0, false))]
+    #[case("fun f(a, b) {}", (2, 2, false))]
+    #[case("fun f(a, b = 1, c = 2) {}", (1, 3, false))]
+    #[case("fun f(...rest) {}", (0, 0, true))]
+    #[case("fun f(a, b = 1, ...rest) {}", (1, 2, true))]
+    #[case("var f = (a = (1), ...rest) => rest;", (0, 1, true))]
+    fn test_arity(#[case] source: &str, #[case] expected: (u8, u8, bool)) {
+        let function = compile_function(source);
+
+        assert_eq!(
+            (function.min_arity, function.max_arity, function.variadic),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case("(a) + 1;")]
+    #[case("(a)\n=> a;")]
+    fn test_arrow_lookahead(#[case] source: &str) {
+        assert!(Compiler::new().compile(source).is_ok());
+    }
+
+    #[rstest]
+    fn test_arrow_lookahead_rejects_tuples() {
+        assert_eq!(
+            compile_errors("(a, b);"),
+            vec!["[line 1] Error at ',': Expected ')' after expression."]
+        );
+    }
+
+    #[rstest]
+    #[case(
+        "return 1;",
+        "[line 1] Error at 'return': Can't return from top-level code."
+    )]
+    #[case("fun (a) {}", "[line 1] Error at '(': Expected function name")]
+    #[case(
+        "var f = (a, 1) => a;",
+        "[line 1] Error at '1': Expect parameter name."
+    )]
+    #[case("var f = (a) => ;", "[line 1] Error at ';': Expected expression")]
+    #[case("f(1, 2;", "[line 1] Error at ';': Expect ')' after arguments.")]
+    #[case(
+        "fun f(a = 1, b) {}",
+        "[line 1] Error at 'b': Parameter without a default value can't follow one with a default."
+    )]
+    #[case(
+        "fun f(...r, a) {}",
+        "[line 1] Error at 'a': Rest parameter must be the last parameter."
+    )]
+    #[case(
+        "fun f(a, ...r, ...s) {}",
+        "[line 1] Error at '...': Rest parameter must be the last parameter."
+    )]
+    #[case(
+        "fun f(...r = []) {}",
+        "[line 1] Error at '=': Rest parameter can't have a default value."
+    )]
+    #[case(
+        "var f = (...) => 1;",
+        "[line 1] Error at ')': Expect parameter name after '...'."
+    )]
+    fn test_function_errors(#[case] source: &str, #[case] error: &str) {
+        assert_eq!(compile_errors(source), vec![error]);
+    }
+
+    #[rstest]
+    #[case(
+        "fun adder(n) { return (x) => x + n; }",
+        "[line 1] Error at 'n': Can't capture local variable 'n' of an enclosing function."
+    )]
+    #[case(
+        "fun f() { var a = 1; fun g() { a = 2; } }",
+        "[line 1] Error at 'a': Can't capture local variable 'a' of an enclosing function."
+    )]
+    #[case(
+        "{ var a = 1; fun f() { a++; } }",
+        "[line 1] Error at 'a': Can't capture local variable 'a' of an enclosing function."
+    )]
+    #[case(
+        "fun f(a) { fun g() { fun h() { print a; } } }",
+        "[line 1] Error at 'a': Can't capture local variable 'a' of an enclosing function."
+    )]
+    fn test_capture_errors(#[case] source: &str, #[case] error: &str) {
+        assert_eq!(compile_errors(source)[0], error);
+    }
+
+    #[rstest]
+    #[case("var n = 1; fun f() { return n; }")]
+    #[case("fun f(n) { return (n) => n; }")]
+    #[case("fun f() { var a = 1; fun g() { var a = 2; return a; } }")]
+    fn test_globals_and_own_locals_are_not_captures(#[case] source: &str) {
+        assert!(Compiler::new().compile(source).is_ok());
+    }
+
+    #[rstest]
+    fn test_try_catch_finally_bytecode() {
+        // Leaving the try block normally pushes nil and Completion::Normal; a value
+        // thrown from the catch block is rethrown at the end of the finally block.
+        assert_eq!(
+            disassemble("try { a; } catch (e) { b; } finally { c; }"),
+            "==<script>==\n\
+             0000    1 OP_PUSH_HANDLER     0 catch -> 16 finally -> 35\n\
+             0006    | OP_GET_GLOBAL       6 'a'\n\
+             0008    | OP_POP\n\
+             0009    | OP_POP_HANDLER\n\
+             0010    | OP_NIL\n\
+             0011    | OP_CONSTANT        11 '0'\n\
+             0013    | OP_JUMP            13 -> 35\n\
+             0016    | OP_PUSH_HANDLER     1 catch -> 33 finally -> 35\n\
+             0022    | OP_GET_GLOBAL      22 'b'\n\
+             0024    | OP_POP\n\
+             0025    | OP_POP_HANDLER\n\
+             0026    | OP_POP\n\
+             0027    | OP_NIL\n\
+             0028    | OP_CONSTANT        28 '0'\n\
+             0030    | OP_JUMP            30 -> 35\n\
+             0033    | OP_CONSTANT        33 '1'\n\
+             0035    | OP_GET_GLOBAL      35 'c'\n\
+             0037    | OP_POP\n\
+             0038    | OP_END_FINALLY\n\
+             0039    | OP_RETURN\n"
+        );
+    }
+
+    #[rstest]
+    #[case(
+        "try { }",
+        "[line 1] Error at end: Expect 'catch' or 'finally' after try block."
+    )]
+    #[case(
+        "try {} catch e {}",
+        "[line 1] Error at 'e': Expect '(' after 'catch'."
+    )]
+    #[case(
+        "try {} catch () {}",
+        "[line 1] Error at ')': Expect exception variable name."
+    )]
+    #[case("throw 1", "[line 1] Error at end: Expect ';' after thrown value.")]
+    #[case("throw;", "[line 1] Error at ';': Expected expression")]
+    fn test_exception_errors(#[case] source: &str, #[case] error: &str) {
+        assert_eq!(compile_errors(source), vec![error]);
+    }
+
+    #[rstest]
+    fn test_const_bytecode() {
+        assert_eq!(
+            disassemble("const a = 1; { const b = a; }"),
+            "==<script>==\n\
+             0000    1 OP_CONSTANT         0 '1'\n\
+             0002    | OP_DEFINE_CONST     2 'a'\n\
+             0004    | OP_GET_GLOBAL       4 'a'\n\
+             0006    | OP_POP\n\
+             0007    | OP_RETURN\n"
+        );
+    }
+
+    #[rstest]
+    #[case(
+        "const a = 1; a = 2;",
+        "[line 1] Error at 'a': Can't assign to a constant."
+    )]
+    #[case(
+        "const a = 1; a += 2;",
+        "[line 1] Error at 'a': Can't assign to a constant."
+    )]
+    #[case(
+        "const a = 1; a++;",
+        "[line 1] Error at 'a': Can't assign to a constant."
+    )]
+    #[case(
+        "const a = 1; --a;",
+        "[line 1] Error at 'a': Can't assign to a constant."
+    )]
+    #[case(
+        "{ const a = 1; a = 2; }",
+        "[line 1] Error at 'a': Can't assign to a constant."
+    )]
+    #[case(
+        "const a = 1; fun f() { a = 2; }",
+        "[line 1] Error at 'a': Can't assign to a constant."
+    )]
+    #[case(
+        "const a = 1; var a = 2;",
+        "[line 1] Error at 'a': Already declared constant with this name."
+    )]
+    #[case(
+        "const a = 1; fun a() {}",
+        "[line 1] Error at 'a': Already declared constant with this name."
+    )]
+    #[case("const a;", "[line 1] Error at ';': Expect '=' after constant name.")]
+    #[case("const = 1;", "[line 1] Error at '=': Expect constant name.")]
+    fn test_const_errors(#[case] source: &str, #[case] error: &str) {
+        assert_eq!(compile_errors(source), vec![error]);
+    }
+
+    #[rstest]
+    #[case("const a = 1; { var a = 2; a = 3; }")]
+    #[case("{ const a = 1; { var a = 2; a = 3; } }")]
+    #[case("const a = 1; fun f(a) { a = 2; }")]
+    #[case("const xs = [1]; xs[0] = 2;")]
+    fn test_shadowed_constants_can_be_assigned(#[case] source: &str) {
+        assert!(Compiler::new().compile(source).is_ok());
+    }
+
+    #[rstest]
+    fn test_import_bytecode() {
+        assert_eq!(
+            disassemble("import \"m.lox\" as m; from \"m.lox\" import a; print m.b;"),
+            "==<script>==\n\
+             0000    1 OP_IMPORT           0 'm.lox'\n\
+             0002    | OP_DEFINE_GLOBAL    2 'm'\n\
+             0004    | OP_IMPORT           4 'm.lox'\n\
+             0006    | OP_GET_PROPERTY     6 'a'\n\
+             0008    | OP_DEFINE_GLOBAL    8 'a'\n\
+             0010    | OP_GET_GLOBAL      10 'm'\n\
+             0012    | OP_GET_PROPERTY    12 'b'\n\
+             0014    | OP_PRINT\n\
+             0015    | OP_RETURN\n"
+        );
+    }
+
+    #[rstest]
+    #[case(
+        "import m as m;",
+        "[line 1] Error at 'm': Expect module path after 'import'."
+    )]
+    #[case(
+        "import \"m.lox\";",
+        "[line 1] Error at ';': Expect 'as' after module path."
+    )]
+    #[case(
+        "import \"m.lox\" as;",
+        "[line 1] Error at ';': Expect module name after 'as'."
+    )]
+    #[case(
+        "import \"m.lox\" as m",
+        "[line 1] Error at end: Expect ';' after import."
+    )]
+    #[case(
+        "from \"m.lox\" a;",
+        "[line 1] Error at 'a': Expect 'import' after module path."
+    )]
+    #[case(
+        "from \"m.lox\" import;",
+        "[line 1] Error at ';': Expect name to import."
+    )]
+    #[case(
+        "from \"m.lox\" import a b;",
+        "[line 1] Error at 'b': Expect ';' after imported names."
+    )]
+    #[case("print m.;", "[line 1] Error at ';': Expect name after '.'.")]
+    #[case("m.a = 1;", "[line 1] Error at '=': Invalid assignment target.")]
+    fn test_import_errors(#[case] source: &str, #[case] error: &str) {
+        assert_eq!(compile_errors(source), vec![error]);
+    }
+}
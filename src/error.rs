@@ -0,0 +1,136 @@
+use crate::value::Value;
+
+/// Why a script stopped at runtime, reported to the user as a Lox runtime error.
+/// The variants after `StepLimit` are only raised by corrupted bytecode.
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum RuntimeError {
+    #[error("Operand must be a number")]
+    OperandNotNumber,
+    #[error("Both operands need to be numbers")]
+    OperandsNotNumbers,
+    #[error("Both operands have to be string or number!")]
+    InvalidAddition,
+    #[error("Operands must be two numbers or two strings")]
+    NotComparable,
+    #[error("Cannot divide by 0!")]
+    DivisionByZero,
+    #[error("Bitwise operands must be integers")]
+    NotInteger,
+    #[error("Bitwise operands must fit in 64 bits")]
+    IntegerOutOfRange,
+    #[error("Shift amount must be between 0 and 63")]
+    InvalidShift,
+    #[error("Only lists and maps can be indexed")]
+    NotIndexable,
+    #[error("List index must be an integer")]
+    InvalidIndex,
+    #[error("Negative list index {0}")]
+    NegativeIndex(f64),
+    #[error("List index {index} out of bounds for length {len}")]
+    IndexOutOfBounds { index: f64, len: usize },
+    #[error("Can only iterate over lists, maps, strings and ranges")]
+    NotIterable,
+    #[error("Range bounds must be integers")]
+    InvalidRange,
+    #[error("Slice {range} out of bounds for length {len}")]
+    SliceOutOfBounds { range: String, len: usize },
+    #[error("Strings can only be indexed by ranges")]
+    InvalidStringIndex,
+    #[error("Only strings can be searched for in a string")]
+    NotSubstring,
+    #[error("Can only test membership in lists, maps, strings and ranges")]
+    NotContainer,
+    #[error("Map keys must be strings, numbers, booleans or nil")]
+    InvalidKey,
+    #[error("NaN can't be used as a map key")]
+    NanKey,
+    #[error("Key {0} not found in map")]
+    MissingKey(String),
+    #[error("Can only call functions")]
+    NotCallable,
+    #[error("Expected {expected} but got {got}")]
+    ArityMismatch { expected: String, got: usize },
+    #[error("Stack overflow")]
+    StackOverflow,
+    #[error("Undefined variable {0}")]
+    UndefinedVariable(String),
+    #[error("Can't assign to constant {0}")]
+    ConstantAssignment(String),
+    #[error("Constant {0} is already defined")]
+    ConstantRedefinition(String),
+    #[error("Only modules have properties")]
+    NotModule,
+    #[error("Undefined variable {name} in module {module}")]
+    UndefinedProperty { module: String, name: String },
+    #[error("Module {0} not found")]
+    ModuleNotFound(String),
+    #[error("Module {module} failed to compile:\n{}", .errors.join("\n"))]
+    ModuleCompileError { module: String, errors: Vec<String> },
+    #[error("Import cycle: {0}")]
+    ImportCycle(String),
+    #[error("{}", uncaught_message(.0))]
+    Thrown(Value),
+    #[error("Step limit exceeded after {0} instructions")]
+    StepLimit(usize),
+    #[error("Unknown opcode {0}")]
+    UnknownOpcode(u8),
+    #[error("Unexpected end of bytecode")]
+    EndOfCode,
+    #[error("Invalid constant index {0}")]
+    InvalidConstant(usize),
+    #[error("Variable name constant is not a string")]
+    InvalidName,
+    #[error("Invalid stack slot {0}")]
+    InvalidSlot(usize),
+    #[error("Jump before the start of the bytecode")]
+    InvalidJump,
+    #[error("Stack underflow")]
+    StackUnderflow,
+    #[error("No exception handler to remove")]
+    NoHandler,
+    #[error("Invalid completion at the end of a finally block")]
+    InvalidCompletion,
+}
+
+impl RuntimeError {
+    /// Whether a `try` block can catch the error. The step limit and corrupted
+    /// bytecode always stop the script.
+    pub fn is_catchable(&self) -> bool {
+        !matches!(
+            self,
+            Self::StepLimit(_)
+                | Self::UnknownOpcode(_)
+                | Self::EndOfCode
+                | Self::InvalidConstant(_)
+                | Self::InvalidName
+                | Self::InvalidSlot(_)
+                | Self::InvalidJump
+                | Self::StackUnderflow
+                | Self::NoHandler
+                | Self::InvalidCompletion
+        )
+    }
+
+    /// The line recorded in a thrown error value, such as one made from a runtime
+    /// error and rethrown at the end of a `finally` block.
+    pub fn thrown_line(&self) -> Option<usize> {
+        let Self::Thrown(Value::Map(map)) = self else {
+            return None;
+        };
+        match map.get(&Value::Str("line".to_string())) {
+            Ok(Value::Number(line)) if line >= 1.0 && line.fract() == 0.0 => Some(line as usize),
+            _ => None,
+        }
+    }
+}
+
+/// The message of a thrown value nothing caught: the message of an error value, such
+/// as those made from runtime errors, or the value itself.
+fn uncaught_message(value: &Value) -> String {
+    if let Value::Map(map) = value {
+        if let Ok(message) = map.get(&Value::Str("message".to_string())) {
+            return message.to_string();
+        }
+    }
+    format!("Uncaught exception: {value}")
+}
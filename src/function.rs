@@ -1,10 +1,17 @@
-use crate::{chunk::*, opcode::OpCode, value::Value};
+use crate::{chunk::*, value::Value};
 use std::cell::RefCell;
 
 use std::{fmt::Display, io::Write};
 #[derive(Debug)]
 pub struct Function {
-    arity: u8,
+    /// Number of parameters without a default value.
+    pub min_arity: u8,
+    /// Number of parameters before the rest parameter, if any.
+    pub max_arity: u8,
+    /// Whether a rest parameter collects the arguments beyond `max_arity`.
+    pub variadic: bool,
+    /// Index of the module the function was declared in, whose globals it uses.
+    pub module: usize,
     pub name: String,
     pub chunk: RefCell<Chunk>,
 }
@@ -15,37 +22,33 @@ impl Display for Function {
     }
 }
 
-impl PartialEq for Function {
-    fn eq(&self, _other: &Self) -> bool {
-        false
-    }
-}
-
-impl PartialOrd for Function {
-    fn partial_cmp(&self, _: &Self) -> Option<std::cmp::Ordering> {
-        panic!("Cannot compare 2 functions")
-    }
-}
-
-impl Clone for Function {
-    fn clone(&self) -> Self {
-        Function {
-            arity: self.arity,
-            name: self.name.clone(),
-            chunk: RefCell::new(self.chunk.borrow().clone()),
-        }
-    }
-}
-
 impl Function {
     pub fn new(name: &str) -> Self {
         Self {
-            arity: 0,
+            min_arity: 0,
+            max_arity: 0,
+            variadic: false,
+            module: 0,
             name: name.to_string(),
             chunk: Chunk::new().into(),
         }
     }
 
+    /// Whether a call may pass `count` arguments.
+    pub fn accepts(&self, count: usize) -> bool {
+        count >= self.min_arity as usize && (self.variadic || count <= self.max_arity as usize)
+    }
+
+    /// The number of arguments a call may pass, as written in arity errors.
+    pub fn expected_arguments(&self) -> String {
+        let noun = |count: u8| if count == 1 { "argument" } else { "arguments" };
+        match (self.min_arity, self.max_arity, self.variadic) {
+            (min, _, true) => format!("at least {min} {}", noun(min)),
+            (min, max, false) if min == max => format!("{min} {}", noun(min)),
+            (min, max, false) => format!("{min} to {max} {}", noun(max)),
+        }
+    }
+
     pub fn size(&self) -> usize {
         self.chunk.borrow().size()
     }
@@ -74,23 +77,30 @@ impl Function {
         self.chunk.borrow().disassemble(&self.name, output)
     }
 
-    pub fn disassemble_instruction(&self, offset: usize, output: &mut impl Write) {
-        self.chunk.borrow().disassemble_instruction(offset, output);
+    /// Disassembles this function followed by every function nested in its constants.
+    pub fn disassemble_all(&self, output: &mut impl Write) {
+        self.disassemble(output);
+        let chunk = self.chunk.borrow();
+        for constant in chunk.constants() {
+            if let Value::Func(function) = constant {
+                function.disassemble_all(output);
+            }
+        }
     }
 
-    pub fn read(&self, ip: usize) -> OpCode {
-        self.chunk.borrow().read(ip)
+    pub fn disassemble_instruction(&self, offset: usize, output: &mut impl Write) {
+        self.chunk.borrow().disassemble_instruction(offset, output);
     }
 
-    pub fn jump_offset(&self, ip: usize) -> usize {
-        self.chunk.borrow().jump_offset(ip)
+    pub fn read_byte(&self, ip: usize) -> Option<u8> {
+        self.chunk.borrow().read_byte(ip)
     }
 
-    pub fn read_constant(&self, index: usize) -> Value {
+    pub fn read_constant(&self, index: usize) -> Option<Value> {
         self.chunk.borrow().read_constant(index)
     }
 
-    pub fn read_line(&self, index: usize) -> usize {
+    pub fn read_line(&self, index: usize) -> Option<usize> {
         self.chunk.borrow().read_line(index)
     }
 }
@@ -98,5 +108,23 @@ impl Function {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::opcode::OpCode;
     use rstest::*;
+
+    #[rstest]
+    fn test_disassemble_all_includes_nested_functions() {
+        let mut inner = Function::new("inner");
+        inner.emit_byte(OpCode::Return.into(), 1);
+        let mut outer = Function::new("outer");
+        outer.emit_constant(Value::Func(inner.into()), 1);
+        outer.emit_byte(OpCode::Return.into(), 1);
+
+        let mut output = Vec::new();
+        outer.disassemble_all(&mut output);
+        let text = String::from_utf8(output).unwrap();
+
+        assert!(text.contains("==outer=="));
+        assert!(text.contains("==inner=="));
+        assert!(text.find("==outer==") < text.find("==inner=="));
+    }
 }
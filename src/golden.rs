@@ -0,0 +1,322 @@
+use crate::{scanner::dump_tokens, vm::*};
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Scripts below directories with these names are not executed: their expected
+/// output is the token dump of the scanner.
+const TOKEN_DIRS: [&str; 1] = ["scanning"];
+
+/// Scripts below directories with these names are modules imported by other
+/// scripts and are not run on their own.
+const MODULE_DIRS: [&str; 1] = ["modules"];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mode {
+    Interpret,
+    Tokens,
+}
+
+/// What a script declares about its own behaviour through its comments:
+///
+/// * `// expect: <line>` - a line printed by the script, in order,
+/// * `// expect runtime error: <message>` - the script stops with a runtime error
+///   reported on the line of the comment,
+/// * `// Error at '<lexeme>': <message>` or `// [line N] Error ...` - a compile error
+///   reported on the line of the comment or on line `N`. The `[c line N]` form of the
+///   upstream test suite is accepted, the `[java line N]` form is ignored.
+#[derive(Debug, Default, PartialEq)]
+pub struct Expectations {
+    pub output: Vec<String>,
+    pub compile_errors: Vec<String>,
+    pub runtime_error: Option<(String, usize)>,
+}
+
+impl Expectations {
+    pub fn parse(source: &str) -> Self {
+        let mut expectations = Expectations::default();
+        for (index, line) in source.lines().enumerate() {
+            let line_number = index + 1;
+            let Some(position) = line.find("// ") else {
+                continue;
+            };
+            let comment = &line[position + 3..];
+
+            if let Some(output) = comment.strip_prefix("expect: ") {
+                expectations.output.push(output.to_string());
+            } else if let Some(message) = comment.strip_prefix("expect runtime error: ") {
+                expectations.runtime_error = Some((message.to_string(), line_number));
+            } else if comment.starts_with("Error") {
+                expectations
+                    .compile_errors
+                    .push(format!("[line {line_number}] {comment}"));
+            } else if let Some(error) = Self::parse_located_error(comment) {
+                expectations.compile_errors.push(error);
+            }
+        }
+        expectations
+    }
+
+    fn parse_located_error(comment: &str) -> Option<String> {
+        let rest = comment
+            .strip_prefix("[line ")
+            .or_else(|| comment.strip_prefix("[c line "))?;
+        let (line, error) = rest.split_once("] ")?;
+        let line: usize = line.parse().ok()?;
+        error
+            .starts_with("Error")
+            .then(|| format!("[line {line}] {error}"))
+    }
+}
+
+/// Everything a script produced when interpreted.
+#[derive(Debug)]
+pub struct Outcome {
+    pub output: Vec<String>,
+    pub errors: Vec<String>,
+    pub result: Result<(), InterpretResult>,
+}
+
+/// A writer whose output can still be read after it was handed to a VM.
+#[derive(Clone, Default)]
+pub struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Capture {
+    pub fn lines(&self) -> Vec<String> {
+        String::from_utf8_lossy(&self.0.borrow())
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub fn interpret(source: &str) -> Outcome {
+    interpret_at(None, source)
+}
+
+/// Interprets `source`, read from the file at `path` if any, so that its imports are
+/// resolved relative to that file.
+pub fn interpret_at(path: Option<&Path>, source: &str) -> Outcome {
+    let out = Capture::default();
+    let err = Capture::default();
+    let mut vm = VM::with_output(Box::new(out.clone()), Box::new(err.clone()));
+    let result = match path {
+        Some(path) => vm.interpret_file(path, source),
+        None => vm.interpret(source),
+    };
+    vm.free();
+    Outcome {
+        output: out.lines(),
+        errors: err.lines(),
+        result,
+    }
+}
+
+pub fn tokenize(source: &str) -> Outcome {
+    let mut out = Capture::default();
+    let mut err = Capture::default();
+    let valid = dump_tokens(source, false, &mut out, &mut err).unwrap();
+    Outcome {
+        output: out.lines(),
+        errors: err.lines(),
+        result: if valid {
+            Ok(())
+        } else {
+            Err(InterpretResult::CompilerError)
+        },
+    }
+}
+
+/// Compares the outcome of a script with its expectations, returning a description
+/// of every mismatch.
+pub fn verify(expectations: &Expectations, outcome: &Outcome) -> Vec<String> {
+    let mut failures = Vec::new();
+
+    if !expectations.compile_errors.is_empty() {
+        if outcome.result != Err(InterpretResult::CompilerError) {
+            failures.push("Expected a compile error.".to_string());
+        }
+        for expected in &expectations.compile_errors {
+            if !outcome.errors.contains(expected) {
+                failures.push(format!("Missing expected error: {expected}"));
+            }
+        }
+        for actual in &outcome.errors {
+            if !expectations.compile_errors.contains(actual) {
+                failures.push(format!("Unexpected error: {actual}"));
+            }
+        }
+    } else if let Some((message, line)) = &expectations.runtime_error {
+        if outcome.result != Err(InterpretResult::RuntimeError) {
+            failures.push(format!("Expected runtime error '{message}'."));
+        }
+        match outcome.errors.first() {
+            Some(actual) if actual == message => {}
+            Some(actual) => failures.push(format!(
+                "Expected runtime error '{message}' and got: {actual}"
+            )),
+            None => failures.push(format!("Expected runtime error '{message}' and got none.")),
+        }
+        let trace = format!("[line {line}]");
+        if !outcome.errors.iter().any(|error| error.starts_with(&trace)) {
+            failures.push(format!("Expected runtime error on line {line}."));
+        }
+    } else {
+        if outcome.result.is_err() {
+            failures.push("Expected the script to succeed.".to_string());
+        }
+        for actual in &outcome.errors {
+            failures.push(format!("Unexpected error: {actual}"));
+        }
+    }
+
+    for (index, expected) in expectations.output.iter().enumerate() {
+        match outcome.output.get(index) {
+            Some(actual) if actual == expected => {}
+            Some(actual) => {
+                failures.push(format!("Expected output '{expected}' and got '{actual}'."))
+            }
+            None => failures.push(format!("Missing expected output '{expected}'.")),
+        }
+    }
+    for actual in outcome.output.iter().skip(expectations.output.len()) {
+        failures.push(format!("Got output '{actual}' when none was expected."));
+    }
+
+    failures
+}
+
+/// Runs every `.lox` file below `dir` and writes a report to `report`.
+/// Returns whether all scripts met their expectations.
+pub fn run_dir(dir: &Path, report: &mut impl Write) -> io::Result<bool> {
+    let mut files = Vec::new();
+    collect_scripts(dir, Mode::Interpret, &mut files)?;
+
+    let mut failed = 0;
+    for (file, mode) in &files {
+        let source = std::fs::read_to_string(file)?;
+        let outcome = match mode {
+            Mode::Interpret => interpret_at(Some(file), &source),
+            Mode::Tokens => tokenize(&source),
+        };
+        let failures = verify(&Expectations::parse(&source), &outcome);
+        if failures.is_empty() {
+            writeln!(report, "PASS {}", file.display())?;
+        } else {
+            failed += 1;
+            writeln!(report, "FAIL {}", file.display())?;
+            for failure in failures {
+                writeln!(report, "     {failure}")?;
+            }
+        }
+    }
+
+    writeln!(report, "{} passed, {} failed", files.len() - failed, failed)?;
+    Ok(failed == 0)
+}
+
+fn collect_scripts(dir: &Path, mode: Mode, files: &mut Vec<(PathBuf, Mode)>) -> io::Result<()> {
+    let mut entries = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<io::Result<Vec<PathBuf>>>()?;
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            let named = |dirs: &[&str]| {
+                path.file_name()
+                    .is_some_and(|name| dirs.iter().any(|dir| name == *dir))
+            };
+            if named(&MODULE_DIRS) {
+                continue;
+            }
+            let tokens = named(&TOKEN_DIRS);
+            let mode = if tokens { Mode::Tokens } else { mode };
+            collect_scripts(&path, mode, files)?;
+        } else if path.extension().is_some_and(|ext| ext == "lox") {
+            files.push((path, mode));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    fn test_parse_expectations() {
+        let source = "print 1; // expect: 1\n\
+                      print x; // expect runtime error: Undefined variable x\n\
+                      var = 1; // Error at '=': Expect variable name.\n\
+                      // [line 7] Error at end: Expect expression.\n\
+                      // [c line 8] Error at 'x': Oops.\n\
+                      // [java line 9] Error at 'x': Ignored.\n";
+
+        let expectations = Expectations::parse(source);
+
+        assert_eq!(expectations.output, vec!["1"]);
+        assert_eq!(
+            expectations.runtime_error,
+            Some(("Undefined variable x".to_string(), 2))
+        );
+        assert_eq!(
+            expectations.compile_errors,
+            vec![
+                "[line 3] Error at '=': Expect variable name.",
+                "[line 7] Error at end: Expect expression.",
+                "[line 8] Error at 'x': Oops.",
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case("print 1;\nprint 2;", "// expect: 1\n// expect: 2")]
+    #[case("print nil;", "// expect: nil")]
+    #[case("print x;", "// expect runtime error: Undefined variable x")]
+    #[case("var = 1;", "// Error at '=': Expect variable name.")]
+    fn test_matching_outcome(#[case] source: &str, #[case] annotations: &str) {
+        let expectations = Expectations::parse(annotations);
+
+        assert_eq!(
+            verify(&expectations, &interpret(source)),
+            Vec::<String>::new()
+        );
+    }
+
+    #[rstest]
+    fn test_matching_token_dump() {
+        let expectations = Expectations::parse("// expect: AND and null\n// expect: EOF  null");
+
+        assert_eq!(
+            verify(&expectations, &tokenize("and")),
+            Vec::<String>::new()
+        );
+    }
+
+    #[rstest]
+    #[case("print 1;", "// expect: 2")]
+    #[case("print 1;", "")]
+    #[case("print 1;", "// expect: 1\n// expect: 1")]
+    #[case("print x;", "// expect: x")]
+    #[case("print x;\n", "\n// expect runtime error: Undefined variable x")]
+    #[case("var = 1;", "// Error at 'var': Expect variable name.")]
+    fn test_mismatching_outcome(#[case] source: &str, #[case] annotations: &str) {
+        let expectations = Expectations::parse(annotations);
+
+        assert!(!verify(&expectations, &interpret(source)).is_empty());
+    }
+}
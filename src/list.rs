@@ -0,0 +1,132 @@
+use crate::{
+    error::RuntimeError,
+    value::{Range, Value},
+};
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+/// A Lox list. Copies of a list value share its elements, and two lists are equal
+/// only when they are the same list.
+#[derive(Debug, Clone, Default)]
+pub struct List(Rc<RefCell<Vec<Value>>>);
+
+impl PartialEq for List {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl List {
+    pub fn new(values: Vec<Value>) -> Self {
+        Self(Rc::new(RefCell::new(values)))
+    }
+
+    pub fn get(&self, index: &Value) -> Result<Value, RuntimeError> {
+        let position = self.position(index)?;
+        Ok(self.0.borrow()[position].clone())
+    }
+
+    pub fn contains(&self, value: &Value) -> bool {
+        self.0.borrow().contains(value)
+    }
+
+    /// A new list with the elements selected by `range`.
+    pub fn slice(&self, range: &Range) -> Result<List, RuntimeError> {
+        let bounds = range.slice_bounds(self.0.borrow().len())?;
+        Ok(List::new(self.0.borrow()[bounds].to_vec()))
+    }
+
+    /// The element at `position`, if the list is still that long.
+    pub fn at(&self, position: usize) -> Option<Value> {
+        self.0.borrow().get(position).cloned()
+    }
+
+    pub fn set(&self, index: &Value, value: Value) -> Result<(), RuntimeError> {
+        let position = self.position(index)?;
+        self.0.borrow_mut()[position] = value;
+        Ok(())
+    }
+
+    /// Checks that `index` designates an element: an integral number from 0 to the
+    /// length of the list, exclusive.
+    fn position(&self, index: &Value) -> Result<usize, RuntimeError> {
+        let Value::Number(index) = *index else {
+            return Err(RuntimeError::InvalidIndex);
+        };
+        let len = self.0.borrow().len();
+        if index.fract() != 0.0 {
+            Err(RuntimeError::InvalidIndex)
+        } else if index < 0.0 {
+            Err(RuntimeError::NegativeIndex(index))
+        } else if index >= len as f64 {
+            Err(RuntimeError::IndexOutOfBounds { index, len })
+        } else {
+            Ok(index as usize)
+        }
+    }
+
+    /// Writes the elements as `[a, b]`. `open` holds the lists and maps being written
+    /// around this one, so a list containing itself is written as `[...]`.
+    pub fn write_nested(&self, f: &mut fmt::Formatter<'_>, open: &mut Vec<Value>) -> fmt::Result {
+        let this = Value::List(self.clone());
+        if open.contains(&this) {
+            return write!(f, "[...]");
+        }
+        open.push(this);
+        write!(f, "[")?;
+        for (position, value) in self.0.borrow().iter().enumerate() {
+            if position > 0 {
+                write!(f, ", ")?;
+            }
+            value.write_nested(f, open)?;
+        }
+        open.pop();
+        write!(f, "]")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    fn numbers() -> List {
+        List::new(vec![Value::Number(1.0), Value::Number(2.0)])
+    }
+
+    #[rstest]
+    #[case(Value::Number(0.0), Ok(Value::Number(1.0)))]
+    #[case(Value::Number(1.0), Ok(Value::Number(2.0)))]
+    #[case(Value::Number(-0.0), Ok(Value::Number(1.0)))]
+    #[case(Value::Number(2.0), Err(RuntimeError::IndexOutOfBounds { index: 2.0, len: 2 }))]
+    #[case(Value::Number(-1.0), Err(RuntimeError::NegativeIndex(-1.0)))]
+    #[case(Value::Number(0.5), Err(RuntimeError::InvalidIndex))]
+    #[case(Value::Number(f64::NAN), Err(RuntimeError::InvalidIndex))]
+    #[case(Value::Str("0".to_string()), Err(RuntimeError::InvalidIndex))]
+    fn test_get(#[case] index: Value, #[case] expected: Result<Value, RuntimeError>) {
+        assert_eq!(numbers().get(&index), expected);
+    }
+
+    #[rstest]
+    fn test_copies_share_elements() {
+        let list = numbers();
+        let copy = list.clone();
+
+        copy.set(&Value::Number(0.0), Value::Nil).unwrap();
+
+        assert_eq!(list.get(&Value::Number(0.0)), Ok(Value::Nil));
+        assert_eq!(list, copy);
+        assert_ne!(list, numbers());
+    }
+
+    #[rstest]
+    fn test_display_of_recursive_list() {
+        let list = numbers();
+        list.set(&Value::Number(1.0), Value::List(list.clone()))
+            .unwrap();
+        let outer = List::new(vec![Value::List(list.clone()), Value::List(list)]);
+
+        assert_eq!(Value::List(outer).to_string(), "[[1, [...]], [1, [...]]]");
+    }
+}
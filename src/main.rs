@@ -1,15 +1,22 @@
 use std::{
-    io::{self, stdout, BufRead, Write},
-    path::PathBuf,
+    io::{self, stdout, BufRead, Read, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
 };
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use compiler::{CompilationResult, Compiler};
 use vm::*;
 
 mod chunk;
 mod compiler;
+mod error;
 mod function;
+mod golden;
+mod list;
 mod opcode;
+#[cfg(test)]
+mod rng;
 mod scanner;
 mod token;
 mod value;
@@ -18,40 +25,144 @@ mod vm;
 #[derive(Parser)]
 #[command(version, about, long_about= None)]
 struct Cli {
-    //path to .lox file for compilation
-    filename: Option<PathBuf>,
-    chunk_type: Option<bool>,
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-fn main() {
-    // add command line parameter to select the chunk implementation
-
-    let mut vm = VM::new();
+#[derive(Subcommand)]
+enum Command {
+    /// Compile and execute a .lox file (`-` reads the program from stdin)
+    Run {
+        filename: PathBuf,
+        /// Also look for imported modules in this directory (repeatable)
+        #[arg(short = 'I', long = "include")]
+        search_path: Vec<PathBuf>,
+    },
+    /// Start the interactive prompt (default when no command is given)
+    Repl,
+    /// Compile a .lox file and report diagnostics without running it
+    Check { filename: PathBuf },
+    /// Print the bytecode of every function in a .lox file
+    Disasm { filename: PathBuf },
+    /// Print the token stream produced by the scanner
+    Tokens {
+        filename: PathBuf,
+        /// Also print `///` doc comments
+        #[arg(long)]
+        trivia: bool,
+    },
+    /// Execute the code given on the command line
+    Eval {
+        #[arg(short = 'e', long = "expr")]
+        code: String,
+        /// Also look for imported modules in this directory (repeatable)
+        #[arg(short = 'I', long = "include")]
+        search_path: Vec<PathBuf>,
+    },
+    /// Run every .lox file in a directory and compare it with its `// expect:` comments
+    Test { dir: PathBuf },
+}
 
+fn main() -> ExitCode {
     let cli = Cli::parse();
 
-    if let Some(filename) = cli.filename {
-        let path = filename.to_str().expect("Expected non-empty path");
-        run_file(&mut vm, path).expect("Could not run file");
+    let result = match cli.command {
+        Some(Command::Run {
+            filename,
+            search_path,
+        }) => read_source(&filename).map(|source| {
+            let path = (filename != Path::new("-")).then_some(filename.as_path());
+            run(&source, path, search_path)
+        }),
+        Some(Command::Check { filename }) => read_source(&filename).map(|source| check(&source)),
+        Some(Command::Disasm { filename }) => read_source(&filename).map(|source| disasm(&source)),
+        Some(Command::Tokens { filename, trivia }) => {
+            read_source(&filename).and_then(|source| tokens(&source, trivia))
+        }
+        Some(Command::Eval { code, search_path }) => Ok(run(&code, None, search_path)),
+        Some(Command::Test { dir }) => golden::run_dir(&dir, &mut stdout()).map(|passed| {
+            if passed {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            }
+        }),
+        Some(Command::Repl) | None => {
+            println!("Starting Lox Repl");
+            repl();
+            Ok(ExitCode::SUCCESS)
+        }
+    };
+
+    result.unwrap_or_else(|err| {
+        eprintln!("I/O error: {err}");
+        ExitCode::from(74)
+    })
+}
+
+fn read_source(path: &Path) -> io::Result<String> {
+    if path == Path::new("-") {
+        let mut buf = String::new();
+        io::stdin().read_to_string(&mut buf)?;
+        Ok(buf)
     } else {
-        println!("Starting Lox Repl");
-        repl(&mut vm);
+        std::fs::read_to_string(path)
+    }
+}
+
+fn exit_code(result: Result<(), InterpretResult>) -> ExitCode {
+    match result {
+        Err(InterpretResult::CompilerError) => ExitCode::from(65),
+        Err(InterpretResult::RuntimeError) => ExitCode::from(70),
+        Ok(_) => ExitCode::SUCCESS,
     }
+}
 
+/// Runs `source`, read from the file at `path` if any: modules are imported relative
+/// to that file or to the current directory, then from `search_path`.
+fn run(source: &str, path: Option<&Path>, search_path: Vec<PathBuf>) -> ExitCode {
+    let mut vm = VM::new();
+    vm.set_search_path(search_path);
+    let res = match path {
+        Some(path) => vm.interpret_file(path, source),
+        None => vm.interpret(source),
+    };
     vm.free();
+    exit_code(res)
+}
+
+fn compile(source: &str) -> Result<CompilationResult, InterpretResult> {
+    let mut compiler = Compiler::new();
+    let res = compiler.compile(source);
+    for error in compiler.errors() {
+        eprintln!("{error}");
+    }
+    res
+}
+
+fn check(source: &str) -> ExitCode {
+    exit_code(compile(source).map(|_| ()))
 }
 
-fn run_file(vm: &mut VM, path: &str) -> io::Result<()> {
-    let buf = std::fs::read_to_string(path)?;
-    let res = vm.interpret(&buf);
-    match res {
-        Err(InterpretResult::CompilerError) => std::process::exit(65),
-        Err(InterpretResult::RuntimeError) => std::process::exit(70),
-        Ok(_) => std::process::exit(0),
+fn disasm(source: &str) -> ExitCode {
+    let res = compile(source);
+    if let Ok(compiled) = &res {
+        compiled.function.disassemble_all(&mut stdout());
     }
+    exit_code(res.map(|_| ()))
 }
 
-fn repl(vm: &mut VM) {
+fn tokens(source: &str, trivia: bool) -> io::Result<ExitCode> {
+    let valid = scanner::dump_tokens(source, trivia, &mut stdout(), &mut io::stderr())?;
+    Ok(if valid {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(65)
+    })
+}
+
+fn repl() {
+    let mut vm = VM::new();
     let stdin = io::stdin();
     print!("lox:>");
     let _ = stdout().flush();
@@ -60,6 +171,13 @@ fn repl(vm: &mut VM) {
             if line.is_empty() {
                 break;
             }
+            if let Some(prefix) = line.strip_prefix(":keywords") {
+                let keywords: Vec<&str> = token::keywords_with_prefix(prefix.trim()).collect();
+                println!("{}", keywords.join(" "));
+                print!("lox:>");
+                let _ = stdout().flush();
+                continue;
+            }
             let result = vm.interpret(&line);
             match result {
                 Err(InterpretResult::CompilerError) => println!("Compilation error!"),
@@ -72,4 +190,5 @@ fn repl(vm: &mut VM) {
         print!("lox:>");
         let _ = stdout().flush();
     }
+    vm.free();
 }
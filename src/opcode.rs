@@ -1,3 +1,4 @@
+use crate::value::Value;
 use std::fmt::Display;
 
 #[derive(Debug, PartialEq, Clone)]
@@ -26,6 +27,36 @@ pub enum OpCode {
     JumpIfFalse,
     Jump,
     Loop,
+    Stringify,
+    Modulo,
+    IntDivide,
+    Power,
+    BitAnd,
+    BitOr,
+    BitXor,
+    BitNot,
+    ShiftLeft,
+    ShiftRight,
+    LessEqual,
+    GreaterEqual,
+    NotEqual,
+    BuildList,
+    IndexGet,
+    IndexSet,
+    BuildMap,
+    ForIter,
+    Range,
+    RangeInclusive,
+    In,
+    Call,
+    JumpIfPassed,
+    Throw,
+    PushHandler,
+    PopHandler,
+    EndFinally,
+    DefineConstant,
+    Import,
+    GetProperty,
 }
 
 impl Display for OpCode {
@@ -33,9 +64,12 @@ impl Display for OpCode {
         write!(f, "OpCode")
     }
 }
-impl From<u8> for OpCode {
-    fn from(value: u8) -> Self {
-        match value {
+/// Decodes an instruction byte, returning the byte itself when it is not an opcode.
+impl TryFrom<u8> for OpCode {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
             0 => Self::Constant,
             1 => Self::Return,
             2 => Self::Negate,
@@ -60,8 +94,38 @@ impl From<u8> for OpCode {
             21 => Self::JumpIfFalse,
             22 => Self::Jump,
             23 => Self::Loop,
-            _ => todo!("Undefined opcode conversion!"),
-        }
+            24 => Self::Stringify,
+            25 => Self::Modulo,
+            26 => Self::IntDivide,
+            27 => Self::Power,
+            28 => Self::BitAnd,
+            29 => Self::BitOr,
+            30 => Self::BitXor,
+            31 => Self::BitNot,
+            32 => Self::ShiftLeft,
+            33 => Self::ShiftRight,
+            34 => Self::LessEqual,
+            35 => Self::GreaterEqual,
+            36 => Self::NotEqual,
+            37 => Self::BuildList,
+            38 => Self::IndexGet,
+            39 => Self::IndexSet,
+            40 => Self::BuildMap,
+            41 => Self::ForIter,
+            42 => Self::Range,
+            43 => Self::RangeInclusive,
+            44 => Self::In,
+            45 => Self::Call,
+            46 => Self::JumpIfPassed,
+            47 => Self::Throw,
+            48 => Self::PushHandler,
+            49 => Self::PopHandler,
+            50 => Self::EndFinally,
+            51 => Self::DefineConstant,
+            52 => Self::Import,
+            53 => Self::GetProperty,
+            _ => return Err(value),
+        })
     }
 }
 
@@ -92,6 +156,81 @@ impl From<OpCode> for u8 {
             OpCode::JumpIfFalse => 21,
             OpCode::Jump => 22,
             OpCode::Loop => 23,
+            OpCode::Stringify => 24,
+            OpCode::Modulo => 25,
+            OpCode::IntDivide => 26,
+            OpCode::Power => 27,
+            OpCode::BitAnd => 28,
+            OpCode::BitOr => 29,
+            OpCode::BitXor => 30,
+            OpCode::BitNot => 31,
+            OpCode::ShiftLeft => 32,
+            OpCode::ShiftRight => 33,
+            OpCode::LessEqual => 34,
+            OpCode::GreaterEqual => 35,
+            OpCode::NotEqual => 36,
+            OpCode::BuildList => 37,
+            OpCode::IndexGet => 38,
+            OpCode::IndexSet => 39,
+            OpCode::BuildMap => 40,
+            OpCode::ForIter => 41,
+            OpCode::Range => 42,
+            OpCode::RangeInclusive => 43,
+            OpCode::In => 44,
+            OpCode::Call => 45,
+            OpCode::JumpIfPassed => 46,
+            OpCode::Throw => 47,
+            OpCode::PushHandler => 48,
+            OpCode::PopHandler => 49,
+            OpCode::EndFinally => 50,
+            OpCode::DefineConstant => 51,
+            OpCode::Import => 52,
+            OpCode::GetProperty => 53,
+        }
+    }
+}
+
+/// How the code protected by a `finally` block was left. `OP_END_FINALLY` finds it
+/// on top of the stack, above the value thrown or returned (`nil` otherwise).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Completion {
+    Normal,
+    Throw,
+    Return,
+}
+
+impl From<Completion> for Value {
+    fn from(completion: Completion) -> Self {
+        Value::Number(completion as u8 as f64)
+    }
+}
+
+impl TryFrom<&Value> for Completion {
+    type Error = ();
+
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Number(n) if *n == 0.0 => Ok(Completion::Normal),
+            Value::Number(n) if *n == 1.0 => Ok(Completion::Throw),
+            Value::Number(n) if *n == 2.0 => Ok(Completion::Return),
+            _ => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    fn test_every_byte_decodes_or_is_rejected() {
+        for byte in 0..=u8::MAX {
+            match OpCode::try_from(byte) {
+                Ok(opcode) => assert_eq!(u8::from(opcode), byte),
+                Err(rejected) => assert_eq!(rejected, byte),
+            }
         }
+        assert_eq!(OpCode::try_from(255), Err(255));
     }
 }
@@ -1,260 +1,637 @@
-use aho_corasick::AhoCorasick;
+use std::io::{self, Write};
 
 use crate::token::*;
-#[derive(Debug)]
-pub struct Scanner {
-    pub source: Vec<char>,
+
+/// Scans the UTF-8 bytes of a borrowed source. Tokens point back into the source with
+/// byte spans, so scanning only allocates for string literal values and error messages.
+#[derive(Debug, Clone)]
+pub struct Scanner<'a> {
+    source: &'a str,
+    bytes: &'a [u8],
     pub line: usize,
+    /// Byte offset of the first character of the current line.
+    line_start: usize,
+    start_line: usize,
+    start_column: usize,
     start: usize,
     current: usize,
-    ac: AhoCorasick,
+    /// Brace depth of every string interpolation `${...}` being scanned, innermost last.
+    interpolations: Vec<usize>,
+    /// Doc comments skipped since the last call to `take_trivia`.
+    trivia: Vec<Trivia>,
+    /// Whether a `//` is integer division instead of a comment. Only the parser
+    /// knows, so it sets this before scanning the token after an operand.
+    integer_division: bool,
+    /// Whether doc comments are kept as trivia, which only tools need.
+    collect_trivia: bool,
 }
 
-impl Scanner {
-    pub fn new(source: &str) -> Scanner {
+impl<'a> Scanner<'a> {
+    pub fn new(source: &'a str) -> Scanner<'a> {
         Scanner {
-            source: source.chars().collect::<Vec<char>>(),
+            source,
+            bytes: source.as_bytes(),
             line: 1,
+            line_start: 0,
+            start_line: 1,
+            start_column: 1,
             current: 0,
             start: 0,
-            ac: AhoCorasick::new([
-                "and", "break", "class", "else", "false", "for", "fun", "if", "nil", "or", "print",
-                "return", "super", "this", "true", "var", "while",
-            ])
-            .unwrap(),
+            interpolations: Vec::new(),
+            trivia: Vec::new(),
+            integer_division: false,
+            collect_trivia: false,
+        }
+    }
+
+    /// A scanner that also keeps the `///` doc comments it skips, returned by
+    /// `take_trivia`.
+    pub fn with_trivia(source: &'a str) -> Scanner<'a> {
+        Scanner {
+            collect_trivia: true,
+            ..Scanner::new(source)
         }
     }
 
+    /// The source text a token was scanned from.
+    pub fn lexeme(&self, token: &Token) -> &'a str {
+        &self.source[token.span.clone()]
+    }
+
+    /// Makes a `//` before the next token, on the same line, integer division rather
+    /// than the start of a comment. A `///` doc comment is never division.
+    pub fn allow_integer_division(&mut self, allow: bool) {
+        self.integer_division = allow;
+    }
+
     pub fn scan_token(&mut self) -> Token {
-        self.skip_whitespace();
+        if let Some(error) = self.skip_whitespace() {
+            return error;
+        }
         self.start = self.current;
+        self.start_line = self.line;
+        self.start_column = self.current - self.line_start + 1;
 
         if self.is_at_end() {
             return self.make_token(TT::EndOfFile);
         };
         let c = self.advance();
         match c {
-            '(' => self.make_token(TT::LeftParen),
-            ')' => self.make_token(TT::RightParen),
-            '{' => self.make_token(TT::LeftBracket),
-            '}' => self.make_token(TT::RightBracket),
-            ';' => self.make_token(TT::Semicolon),
-            ',' => self.make_token(TT::Comma),
-            '.' => self.make_token(TT::Dot),
-            '-' => self.make_token(TT::Minus),
-            '+' => self.make_token(TT::Plus),
-
-            '/' => self.make_token(TT::Slash),
-            '*' => self.make_token(TT::Star),
-            '!' => {
-                let eq = self.matching('=');
+            b'(' => self.make_token(TT::LeftParen),
+            b')' => self.make_token(TT::RightParen),
+            b'[' => self.make_token(TT::LeftBracket),
+            b']' => self.make_token(TT::RightBracket),
+            b'{' => {
+                if let Some(depth) = self.interpolations.last_mut() {
+                    *depth += 1;
+                }
+                self.make_token(TT::LeftBrace)
+            }
+            b'}' => match self.interpolations.last_mut() {
+                Some(0) => {
+                    self.interpolations.pop();
+                    self.string()
+                }
+                Some(depth) => {
+                    *depth -= 1;
+                    self.make_token(TT::RightBrace)
+                }
+                None => self.make_token(TT::RightBrace),
+            },
+            b';' => self.make_token(TT::Semicolon),
+            b',' => self.make_token(TT::Comma),
+            b'.' => {
+                let ttype = if !self.matching(b'.') {
+                    TT::Dot
+                } else if self.matching(b'=') {
+                    TT::DotDotEquals
+                } else if self.matching(b'.') {
+                    TT::DotDotDot
+                } else {
+                    TT::DotDot
+                };
+                self.make_token(ttype)
+            }
+            b'-' => {
+                let ttype = if self.matching(b'-') {
+                    TT::MinusMinus
+                } else if self.matching(b'=') {
+                    TT::MinusAssign
+                } else {
+                    TT::Minus
+                };
+                self.make_token(ttype)
+            }
+            b'+' => {
+                let ttype = if self.matching(b'+') {
+                    TT::PlusPlus
+                } else if self.matching(b'=') {
+                    TT::PlusAssign
+                } else {
+                    TT::Plus
+                };
+                self.make_token(ttype)
+            }
+
+            b'/' => {
+                let ttype = if self.matching(b'/') {
+                    TT::SlashSlash
+                } else if self.matching(b'=') {
+                    TT::SlashAssign
+                } else {
+                    TT::Slash
+                };
+                self.make_token(ttype)
+            }
+            b'*' => {
+                let ttype = if self.matching(b'*') {
+                    TT::StarStar
+                } else if self.matching(b'=') {
+                    TT::StarAssign
+                } else {
+                    TT::Star
+                };
+                self.make_token(ttype)
+            }
+            b'%' => {
+                let eq = self.matching(b'=');
+                self.make_token(if eq { TT::PercentAssign } else { TT::Percent })
+            }
+            b'&' => self.make_token(TT::Ampersand),
+            b'|' => self.make_token(TT::Pipe),
+            b'^' => self.make_token(TT::Caret),
+            b'~' => self.make_token(TT::Tilde),
+            b'?' => self.make_token(TT::Question),
+            b':' => self.make_token(TT::Colon),
+            b'!' => {
+                let eq = self.matching(b'=');
                 self.make_token(if eq { TT::BangEquals } else { TT::Bang })
             }
-            '=' => {
-                let eq = self.matching('=');
-                self.make_token(if eq { TT::Equals } else { TT::Assign })
+            b'=' => {
+                let ttype = if self.matching(b'=') {
+                    TT::Equals
+                } else if self.matching(b'>') {
+                    TT::Arrow
+                } else {
+                    TT::Assign
+                };
+                self.make_token(ttype)
             }
-            '<' => {
-                let eq = self.matching('=');
-                self.make_token(if eq { TT::LessEquals } else { TT::Less })
+            b'<' => {
+                let ttype = if self.matching(b'=') {
+                    TT::LessEquals
+                } else if self.matching(b'<') {
+                    TT::LessLess
+                } else {
+                    TT::Less
+                };
+                self.make_token(ttype)
             }
-            '>' => {
-                let eq = self.matching('=');
-                self.make_token(if eq {
+            b'>' => {
+                let ttype = if self.matching(b'=') {
                     TT::GreaterEquals
+                } else if self.matching(b'>') {
+                    TT::GreaterGreater
                 } else {
-                    {
-                        TT::Greater
-                    }
-                })
+                    TT::Greater
+                };
+                self.make_token(ttype)
+            }
+            b'0'..=b'9' => self.number(),
+            b'"' => self.string(),
+            b'_' | b'a'..=b'z' | b'A'..=b'Z' => self.identifier(),
+            _ if c.is_ascii() => self.error_token(format!("Unexpected character '{}'", c as char)),
+            _ => {
+                let c = self.char_at(self.start);
+                self.current = self.start + c.len_utf8();
+                if c.is_alphabetic() {
+                    self.identifier()
+                } else {
+                    self.error_token(format!("Unexpected character '{c}'"))
+                }
             }
-            '0'..='9' => self.number(),
-            '"' => self.string(),
-            _ if c.is_alphabetic() || c == '_' => self.identifier(),
-            _ => self.error_token("Unexpected token"),
         }
     }
-    fn advance(&mut self) -> char {
+    fn advance(&mut self) -> u8 {
         self.current += 1;
-        self.source[self.current - 1]
+        self.bytes[self.current - 1]
     }
 
-    fn matching(&mut self, expected: char) -> bool {
+    fn matching(&mut self, expected: u8) -> bool {
         if self.is_at_end() {
             return false;
         }
-        if self.source[self.current] != expected {
+        if self.bytes[self.current] != expected {
             return false;
         }
         self.current += 1;
         true
     }
-    fn peek(&self) -> char {
+    fn peek(&self) -> u8 {
         if self.is_at_end() {
-            '\0'
+            b'\0'
         } else {
-            self.source[self.current]
+            self.bytes[self.current]
         }
     }
 
-    fn peek_next(&mut self) -> Option<char> {
-        if self.is_at_end() {
-            None
+    fn peek_next(&mut self) -> Option<u8> {
+        self.bytes.get(self.current + 1).copied()
+    }
+
+    /// Decodes the character starting at byte `offset`, which has to be a char boundary.
+    fn char_at(&self, offset: usize) -> char {
+        self.source[offset..].chars().next().unwrap_or('\0')
+    }
+
+    /// Length in bytes of the identifier character at the current position, if any.
+    fn identifier_char_len(&self) -> Option<usize> {
+        let c = self.peek();
+        if c.is_ascii_alphanumeric() || c == b'_' {
+            Some(1)
+        } else if !c.is_ascii() {
+            let c = self.char_at(self.current);
+            c.is_alphanumeric().then(|| c.len_utf8())
         } else {
-            Some(self.source[self.current + 1])
+            None
         }
     }
 
-    fn peek_is_alphanumeric(&self) -> bool {
-        let c = self.peek();
-        c.is_alphanumeric() || c == '_'
+    /// Called after consuming a `\n` to start counting the next line. A `//` on a
+    /// new line always starts a comment.
+    fn newline(&mut self) {
+        self.line += 1;
+        self.line_start = self.current;
+        self.integer_division = false;
     }
 
-    fn skip_whitespace(&mut self) {
+    /// Returns the `///` doc comments skipped since the previous call, in source order.
+    /// Always empty unless the scanner was created by `with_trivia`.
+    pub fn take_trivia(&mut self) -> Vec<Trivia> {
+        std::mem::take(&mut self.trivia)
+    }
+
+    fn skip_whitespace(&mut self) -> Option<Token> {
         loop {
             match self.peek() {
-                ' ' | '\r' | '\t' => {
+                b' ' | b'\r' | b'\t' => {
                     self.advance();
                 }
-                '\n' => {
-                    self.line += 1;
+                b'\n' => {
                     self.advance();
+                    self.newline();
                 }
-                '/' => {
-                    if let Some('/') = self.peek_next() {
-                        while self.peek() != '\n' && !self.is_at_end() {
-                            self.advance();
+                b'/' => match self.peek_next() {
+                    Some(b'/')
+                        if self.integer_division
+                            && self.bytes.get(self.current + 2) != Some(&b'/') =>
+                    {
+                        return None
+                    }
+                    Some(b'/') => self.line_comment(),
+                    Some(b'*') => {
+                        if let Some(error) = self.block_comment() {
+                            return Some(error);
                         }
-                    } else {
-                        return;
                     }
-                }
+                    _ => return None,
+                },
                 _ => {
-                    return;
+                    return None;
                 }
             }
         }
     }
 
-    fn identifier(&mut self) -> Token {
-        while self.peek_is_alphanumeric() {
+    fn line_comment(&mut self) {
+        let start = self.current;
+        while self.peek() != b'\n' && !self.is_at_end() {
             self.advance();
         }
+        if !self.collect_trivia {
+            return;
+        }
+        let text = &self.source[start..self.current];
+        if text.starts_with("///") && !text.starts_with("////") {
+            self.trivia.push(Trivia {
+                line: self.line,
+                span: start..self.current,
+            });
+        }
+    }
+
+    /// Skips a `/* ... */` comment, which may contain nested block comments.
+    /// Returns an error token located at the opening `/*` when it is never closed.
+    fn block_comment(&mut self) -> Option<Token> {
+        self.start = self.current;
+        self.start_line = self.line;
+        self.start_column = self.current - self.line_start + 1;
+        self.advance();
+        self.advance();
+        let mut depth = 1;
+        while depth > 0 {
+            if self.is_at_end() {
+                return Some(self.error_token("Unterminated block comment".to_string()));
+            }
+            match (self.advance(), self.peek()) {
+                (b'/', b'*') => {
+                    self.advance();
+                    depth += 1;
+                }
+                (b'*', b'/') => {
+                    self.advance();
+                    depth -= 1;
+                }
+                (b'\n', _) => self.newline(),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    fn identifier(&mut self) -> Token {
+        while let Some(len) = self.identifier_char_len() {
+            self.current += len;
+        }
 
         self.make_token(self.identifier_type())
     }
     fn identifier_type(&self) -> TokenType {
-        let haystack = self.source[self.start..self.current]
-            .iter()
-            .collect::<String>();
-        let mut matches = Vec::new();
-        for mat in self.ac.find_iter(&haystack) {
-            matches.push(mat)
-        }
-        if matches.is_empty() {
-            return TT::Identifier;
-        }
-        match haystack.as_str() {
-            "and" => TT::And,
-            "false" => TT::Constant,
-            "true" => TT::Constant,
-            "nil" => TT::Constant,
-            "while" => TT::While,
-            "for" => TT::For,
-            "fun" => TT::Fun,
-            "class" => TT::Class,
-            "super" => TT::Super,
-            "this" => TT::This,
-            "or" => TT::Or,
-            "else" => TT::Else,
-            "print" => TT::Print,
-            "return" => TT::Return,
-            "var" => TT::Var,
-            "if" => TT::If,
-            "break" => TT::Break,
-
-            _ => TT::Error,
-        }
+        keyword(&self.source[self.start..self.current]).unwrap_or(TT::Identifier)
     }
 
+    /// Scans a number literal: decimal with optional fraction and exponent
+    /// (`6.02E23`, `1e-9`), or an integer with a `0x`, `0b` or `0o` prefix. Digits may be
+    /// separated by single underscores (`1_000_000`).
     fn number(&mut self) -> Token {
-        while self.peek().is_ascii_digit() {
+        let radix = match (self.bytes[self.start], self.peek()) {
+            (b'0', b'x' | b'X') => Some((16, "hexadecimal")),
+            (b'0', b'b' | b'B') => Some((2, "binary")),
+            (b'0', b'o' | b'O') => Some((8, "octal")),
+            _ => None,
+        };
+        let value = match radix {
+            Some((radix, name)) => {
+                self.advance();
+                self.integer(radix, name)
+            }
+            None => self.decimal(),
+        };
+        match value {
+            Ok(value) => self.make_literal_token(TT::Number, Literal::Number(value)),
+            Err(message) => {
+                while let Some(len) = self.identifier_char_len() {
+                    self.current += len;
+                }
+                self.error_token(message)
+            }
+        }
+    }
+
+    fn integer(&mut self, radix: u32, name: &str) -> Result<f64, String> {
+        let digits_start = self.current;
+        self.digits(radix, name)?;
+        let digits = &self.source[digits_start..self.current];
+        if digits.is_empty() {
+            let prefix = &self.source[self.start..self.current];
+            return Err(format!("Expected {name} digits after '{prefix}'"));
+        }
+        u64::from_str_radix(&digits.replace('_', ""), radix)
+            .map(|value| value as f64)
+            .map_err(|_| format!("Number literal is too large for a {name} literal"))
+    }
+
+    fn decimal(&mut self) -> Result<f64, String> {
+        self.digits(10, "decimal")?;
+
+        if self.peek() == b'.' && self.peek_next().is_some_and(|c| c.is_ascii_digit()) {
             self.advance();
+            self.digits(10, "decimal")?;
         }
 
-        if self.peek() == '.' && self.peek_next().unwrap().is_ascii_digit() {
+        if matches!(self.peek(), b'e' | b'E') {
             self.advance();
-            while self.peek().is_ascii_digit() {
+            if matches!(self.peek(), b'+' | b'-') {
+                self.advance();
+            }
+            let exponent_start = self.current;
+            self.digits(10, "decimal")?;
+            if self.current == exponent_start {
+                return Err("Expected digits in the exponent of a number literal".to_string());
+            }
+        }
+
+        let literal = &self.source[self.start..self.current];
+        let parsed = if literal.contains('_') {
+            literal.replace('_', "").parse()
+        } else {
+            literal.parse()
+        };
+        parsed.map_err(|_| format!("Invalid number literal '{literal}'"))
+    }
+
+    /// Consumes digits of `radix` and `_` separators. A separator has to be placed
+    /// between two digits.
+    fn digits(&mut self, radix: u32, name: &str) -> Result<(), String> {
+        let is_digit = |c: u8| (c as char).is_digit(radix);
+        let mut after_digit = self.current > self.start && is_digit(self.bytes[self.current - 1]);
+        loop {
+            let c = self.peek();
+            if c == b'_' {
+                if !after_digit || !self.peek_next().is_some_and(is_digit) {
+                    return Err("Digit separator '_' must be placed between digits".to_string());
+                }
+                self.advance();
+                after_digit = false;
+            } else if is_digit(c) {
                 self.advance();
+                after_digit = true;
+            } else if radix != 10 && c.is_ascii_alphanumeric() {
+                return Err(format!("Invalid digit '{}' in {name} literal", c as char));
+            } else {
+                return Ok(());
             }
         }
-        self.make_token(TT::Number)
     }
 
+    /// Scans a string literal, or its next segment when resuming after the `}` of an
+    /// interpolation. A segment ending with `${` becomes an `Interpolation` token, the
+    /// segment closing the literal a `String` token.
     fn string(&mut self) -> Token {
-        while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line += 1;
+        let mut value: Vec<u8> = Vec::new();
+        let mut error: Option<Token> = None;
+        while self.peek() != b'"' && !self.is_at_end() {
+            match self.advance() {
+                b'$' if self.peek() == b'{' => {
+                    self.advance();
+                    self.interpolations.push(0);
+                    return error.unwrap_or_else(|| {
+                        self.make_literal_token(TT::Interpolation, Self::string_literal(value))
+                    });
+                }
+                b'\\' => {
+                    let line = self.line;
+                    let column = self.current - self.line_start;
+                    match self.escape() {
+                        Ok(c) => value.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+                        Err(message) => {
+                            error.get_or_insert(Token {
+                                line,
+                                column,
+                                ..self.error_token(message)
+                            });
+                        }
+                    }
+                }
+                c => {
+                    value.push(c);
+                    if c == b'\n' {
+                        self.newline();
+                    }
+                }
             }
-            self.advance();
         }
         if self.is_at_end() {
-            self.error_token("Unterminated string")
+            self.error_token("Unterminated string".to_string())
         } else {
             self.advance();
-            self.make_token(TT::String)
+            error
+                .unwrap_or_else(|| self.make_literal_token(TT::String, Self::string_literal(value)))
         }
     }
 
-    fn make_token(&self, ttype: TT) -> Token {
-        let mut literal: Option<Literal> = None;
-        let lexeme: String = self.source[self.start..self.current].iter().collect();
-        match ttype {
-            TT::Number => {
-                let value: f64 = lexeme.parse().unwrap();
-                literal = Some(Literal::Number(value));
+    /// The bytes of a string are only ever split at ASCII characters, so they remain
+    /// valid UTF-8.
+    fn string_literal(value: Vec<u8>) -> Literal {
+        Literal::String(String::from_utf8(value).expect("string literal is valid UTF-8"))
+    }
+
+    /// Decodes the escape sequence following a backslash inside a string.
+    fn escape(&mut self) -> Result<char, String> {
+        if self.is_at_end() {
+            return Err("Unterminated escape sequence".to_string());
+        }
+        match self.advance() {
+            b'n' => Ok('\n'),
+            b't' => Ok('\t'),
+            b'r' => Ok('\r'),
+            b'0' => Ok('\0'),
+            b'\\' => Ok('\\'),
+            b'"' => Ok('"'),
+            b'$' => Ok('$'),
+            b'u' => self.unicode_escape(),
+            b'\n' => {
+                self.newline();
+                Err("Invalid escape sequence '\\' at end of line".to_string())
             }
-            TT::String => {
-                let work = lexeme.clone();
-                let value = work.trim_matches('"');
-                literal = Some(Literal::String(value.to_string()));
+            c if c.is_ascii() => Err(format!("Invalid escape sequence '\\{}'", c as char)),
+            _ => {
+                let c = self.char_at(self.current - 1);
+                self.current += c.len_utf8() - 1;
+                Err(format!("Invalid escape sequence '\\{c}'"))
             }
-            TT::Constant => {
-                let value = lexeme.clone();
-                match value.as_str() {
-                    "nil" => literal = Some(Literal::Nil),
-                    "true" => literal = Some(Literal::Boolean(true)),
-                    "false" => literal = Some(Literal::Boolean(false)),
-                    &_ => {
-                        return self.error_token("Unexpected value: ");
-                    }
+        }
+    }
+
+    /// Decodes the `{XXXX}` part of a `\u{XXXX}` escape: 1 to 6 hex digits naming a
+    /// unicode scalar value.
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        if !self.matching(b'{') {
+            return Err("Expected '{' after '\\u'".to_string());
+        }
+        let digits_start = self.current;
+        while self.peek().is_ascii_hexdigit() {
+            self.advance();
+        }
+        let digits = &self.source[digits_start..self.current];
+        if !self.matching(b'}') {
+            return Err("Expected '}' to close unicode escape".to_string());
+        }
+        if digits.is_empty() || digits.len() > 6 {
+            return Err(format!(
+                "Unicode escape must have 1 to 6 hex digits, got '\\u{{{digits}}}'"
+            ));
+        }
+        u32::from_str_radix(digits, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| format!("Invalid unicode scalar value '\\u{{{digits}}}'"))
+    }
+
+    fn make_token(&self, ttype: TT) -> Token {
+        let mut literal: Option<Literal> = None;
+        if ttype == TT::Literal {
+            match &self.source[self.start..self.current] {
+                "nil" => literal = Some(Literal::Nil),
+                "true" => literal = Some(Literal::Boolean(true)),
+                "false" => literal = Some(Literal::Boolean(false)),
+                &_ => {
+                    return self.error_token("Unexpected value".to_string());
                 }
             }
-            _ => {}
-        };
+        }
         Token {
             ttype,
-            line: self.line,
-            lexeme,
+            span: self.start..self.current,
+            line: self.start_line,
+            column: self.start_column,
             literal,
         }
     }
 
-    fn error_token(&self, message: &str) -> Token {
-        Token::new(
-            TT::Error,
-            self.line,
-            format!(
-                "{}: {:?}",
-                message,
-                self.source[self.start..self.current]
-                    .iter()
-                    .collect::<Vec<&char>>()
-            ),
-        )
+    fn make_literal_token(&self, ttype: TT, literal: Literal) -> Token {
+        Token {
+            literal: Some(literal),
+            ..self.make_token(ttype)
+        }
+    }
+
+    fn error_token(&self, message: String) -> Token {
+        Token {
+            ttype: TT::Error,
+            span: self.start..self.current,
+            line: self.start_line,
+            column: self.start_column,
+            literal: Some(Literal::String(message)),
+        }
     }
     fn is_at_end(&self) -> bool {
-        self.current == self.source.len()
+        self.current == self.bytes.len()
+    }
+}
+
+/// Writes the dump of every token in `source` to `out` and scanning errors to `err`.
+/// With `trivia`, doc comments are dumped as `DOC_COMMENT` entries before the token
+/// following them. Returns whether the source was scanned without errors.
+pub fn dump_tokens(
+    source: &str,
+    trivia: bool,
+    out: &mut impl Write,
+    err: &mut impl Write,
+) -> io::Result<bool> {
+    let mut scanner = if trivia {
+        Scanner::with_trivia(source)
+    } else {
+        Scanner::new(source)
+    };
+    let mut valid = true;
+    loop {
+        let token = scanner.scan_token();
+        for doc in scanner.take_trivia() {
+            let text = &source[doc.span];
+            let content = text.trim_start_matches('/').trim();
+            writeln!(out, "DOC_COMMENT {} {}", text, content)?;
+        }
+        match token.ttype {
+            TT::Error => {
+                valid = false;
+                writeln!(err, "[line {}] Error: {}", token.line, token.message())?;
+            }
+            _ => writeln!(out, "{}", token.dump(source))?,
+        }
+        if token.ttype == TT::EndOfFile {
+            return Ok(valid);
+        }
     }
 }
 
@@ -262,6 +639,7 @@ impl Scanner {
 mod tests {
 
     use super::*;
+    use crate::rng::Rng;
     use rstest::*;
 
     #[rstest]
@@ -269,7 +647,394 @@ mod tests {
         let source = "-1";
         let scanner = Scanner::new(source);
 
-        assert_eq!(scanner.source.len(), 2);
+        assert_eq!(scanner.bytes.len(), 2);
+    }
+
+    #[rstest]
+    fn test_dump_tokens() {
+        let mut out = Vec::new();
+        let mut err = Vec::new();
+
+        let valid = dump_tokens("123.\n.456 @", false, &mut out, &mut err).unwrap();
+
+        assert!(!valid);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "NUMBER 123 123.0\nDOT . null\nDOT . null\nNUMBER 456 456.0\nEOF  null\n"
+        );
+        assert!(String::from_utf8(err)
+            .unwrap()
+            .starts_with("[line 2] Error: "));
+    }
+
+    #[rstest]
+    #[case(r#""plain""#, "plain")]
+    #[case(r#""a\nb\tc\rd""#, "a\nb\tc\rd")]
+    #[case(r#""quote \" backslash \\ nul \0""#, "quote \" backslash \\ nul \0")]
+    #[case(r#""\u{41}\u{1F600}\u{e9}""#, "A\u{1F600}\u{e9}")]
+    #[case("\"two\nlines\"", "two\nlines")]
+    fn test_string_escapes(#[case] source: &str, #[case] expected: &str) {
+        let token = Scanner::new(source).scan_token();
+
+        assert_eq!(token.ttype, TT::String);
+        assert_eq!(token.literal, Some(Literal::String(expected.to_string())));
+    }
+
+    #[rstest]
+    #[case(r#""\q""#, 1, "Invalid escape sequence '\\q'")]
+    #[case("\"first\nsecond \\x\"", 2, "Invalid escape sequence '\\x'")]
+    #[case(r#""\u41""#, 1, "Expected '{' after '\\u'")]
+    #[case(
+        r#""\u{}""#,
+        1,
+        "Unicode escape must have 1 to 6 hex digits, got '\\u{}'"
+    )]
+    #[case(
+        r#""\u{1234567}""#,
+        1,
+        "Unicode escape must have 1 to 6 hex digits, got '\\u{1234567}'"
+    )]
+    #[case(r#""\u{D800}""#, 1, "Invalid unicode scalar value '\\u{D800}'")]
+    #[case(r#""\u{41""#, 1, "Expected '}' to close unicode escape")]
+    fn test_invalid_string_escapes(
+        #[case] source: &str,
+        #[case] line: usize,
+        #[case] message: &str,
+    ) {
+        let mut scanner = Scanner::new(source);
+        let token = scanner.scan_token();
+
+        assert_eq!(token.ttype, TT::Error);
+        assert_eq!(token.line, line);
+        assert_eq!(token.message(), message);
+        assert_eq!(scanner.scan_token().ttype, TT::EndOfFile);
+    }
+
+    #[rstest]
+    fn test_multiline_string_lines() {
+        let mut scanner = Scanner::new("\"one\ntwo\nthree\" after\n\"open\n");
+
+        let string = scanner.scan_token();
+        assert_eq!(string.ttype, TT::String);
+        assert_eq!(string.line, 1);
+        let after = scanner.scan_token();
+        assert_eq!(scanner.lexeme(&after), "after");
+        assert_eq!(after.line, 3);
+        let unterminated = scanner.scan_token();
+        assert_eq!(unterminated.ttype, TT::Error);
+        assert_eq!(unterminated.line, 4);
+        assert_eq!(scanner.scan_token().line, 5);
+    }
+
+    #[rstest]
+    fn test_string_interpolation() {
+        let mut scanner = Scanner::new(r#""a ${b + "c ${d}"} { ${ {} } \${e}""#);
+        let expected = [
+            (TT::Interpolation, Some("a ")),
+            (TT::Identifier, None),
+            (TT::Plus, None),
+            (TT::Interpolation, Some("c ")),
+            (TT::Identifier, None),
+            (TT::String, Some("")),
+            (TT::Interpolation, Some(" { ")),
+            (TT::LeftBrace, None),
+            (TT::RightBrace, None),
+            (TT::String, Some(" ${e}")),
+            (TT::EndOfFile, None),
+        ];
+
+        for (ttype, segment) in expected {
+            let token = scanner.scan_token();
+            assert_eq!(token.ttype, ttype);
+            if let Some(segment) = segment {
+                assert_eq!(token.literal, Some(Literal::String(segment.to_string())));
+            }
+        }
+    }
+
+    #[rstest]
+    fn test_block_comments() {
+        let mut scanner = Scanner::new("a /* one\n /* nested\n */ still */ b /**/ c /*/ d */ e");
+
+        for (lexeme, line) in [("a", 1), ("b", 3), ("c", 3), ("e", 3)] {
+            let token = scanner.scan_token();
+            assert_eq!(token.ttype, TT::Identifier);
+            assert_eq!((scanner.lexeme(&token), token.line), (lexeme, line));
+        }
+        assert_eq!(scanner.scan_token().ttype, TT::EndOfFile);
+    }
+
+    #[rstest]
+    fn test_unterminated_block_comment() {
+        let mut scanner = Scanner::new("a\n/* open /* nested */\n\n");
+
+        scanner.scan_token();
+        let error = scanner.scan_token();
+
+        assert_eq!(error.ttype, TT::Error);
+        assert_eq!(error.line, 2);
+        assert_eq!(error.message(), "Unterminated block comment");
+        assert_eq!(error.column, 1);
+        assert_eq!(scanner.scan_token().ttype, TT::EndOfFile);
+    }
+
+    #[rstest]
+    fn test_doc_comments_are_trivia() {
+        let mut scanner =
+            Scanner::with_trivia("/// Adds.\n// plain\n//// banner\nfun /// Second.\nx");
+
+        assert_eq!(scanner.scan_token().ttype, TT::Fun);
+        assert_eq!(
+            scanner.take_trivia(),
+            vec![Trivia {
+                line: 1,
+                span: 0..9
+            }]
+        );
+        assert_eq!(scanner.scan_token().ttype, TT::Identifier);
+        assert_eq!(scanner.take_trivia()[0].line, 4);
+        assert!(scanner.take_trivia().is_empty());
+    }
+
+    #[rstest]
+    fn test_trivia_is_only_collected_on_request() {
+        let mut scanner = Scanner::new("/// Adds.\nfun");
+
+        assert_eq!(scanner.scan_token().ttype, TT::Fun);
+        assert!(scanner.take_trivia().is_empty());
+    }
+
+    #[rstest]
+    fn test_dump_tokens_with_trivia() {
+        let mut out = Vec::new();
+
+        dump_tokens("/// Greets.\nfun", true, &mut out, &mut Vec::new()).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "DOC_COMMENT /// Greets. Greets.\nFUN fun null\nEOF  null\n"
+        );
+    }
+
+    #[rstest]
+    #[case("123", 123.0)]
+    #[case("0", 0.0)]
+    #[case("007", 7.0)]
+    #[case("123.456", 123.456)]
+    #[case("0xFF", 255.0)]
+    #[case("0Xff", 255.0)]
+    #[case("0b1010", 10.0)]
+    #[case("0o755", 493.0)]
+    #[case("1e-9", 1e-9)]
+    #[case("6.02E23", 6.02e23)]
+    #[case("2e+3", 2000.0)]
+    #[case("1_000_000", 1_000_000.0)]
+    #[case("0xFF_FF", 65535.0)]
+    #[case("1_0.2_5e1_0", 10.25e10)]
+    fn test_number_literals(#[case] source: &str, #[case] expected: f64) {
+        let mut scanner = Scanner::new(source);
+        let token = scanner.scan_token();
+
+        assert_eq!(token.ttype, TT::Number);
+        assert_eq!(scanner.lexeme(&token), source);
+        assert_eq!(token.literal, Some(Literal::Number(expected)));
+        assert_eq!(scanner.scan_token().ttype, TT::EndOfFile);
+    }
+
+    #[rstest]
+    #[case("0x", "Expected hexadecimal digits after '0x'")]
+    #[case("0b", "Expected binary digits after '0b'")]
+    #[case("0o;", "Expected octal digits after '0o'")]
+    #[case("0b102", "Invalid digit '2' in binary literal")]
+    #[case("0o8", "Invalid digit '8' in octal literal")]
+    #[case("0xFG", "Invalid digit 'G' in hexadecimal literal")]
+    #[case("1e", "Expected digits in the exponent of a number literal")]
+    #[case("1e+", "Expected digits in the exponent of a number literal")]
+    #[case("1_", "Digit separator '_' must be placed between digits")]
+    #[case("1__0", "Digit separator '_' must be placed between digits")]
+    #[case("0x_1", "Digit separator '_' must be placed between digits")]
+    #[case("1_.5", "Digit separator '_' must be placed between digits")]
+    #[case(
+        "0x1_0000_0000_0000_0000",
+        "Number literal is too large for a hexadecimal literal"
+    )]
+    fn test_malformed_number_literals(#[case] source: &str, #[case] message: &str) {
+        let mut scanner = Scanner::new(source);
+        let token = scanner.scan_token();
+
+        assert_eq!(token.ttype, TT::Error);
+        assert_eq!(token.message(), message);
+    }
+
+    #[rstest]
+    #[case("1.", &[(TT::Number, "1"), (TT::Dot, ".")])]
+    #[case(".5", &[(TT::Dot, "."), (TT::Number, "5")])]
+    #[case("1.e5", &[(TT::Number, "1"), (TT::Dot, "."), (TT::Identifier, "e5")])]
+    #[case("0x1.5", &[(TT::Number, "0x1"), (TT::Dot, "."), (TT::Number, "5")])]
+    #[case("12abc", &[(TT::Number, "12"), (TT::Identifier, "abc")])]
+    fn test_number_boundaries(#[case] source: &str, #[case] expected: &[(TT, &str)]) {
+        let mut scanner = Scanner::new(source);
+
+        for (ttype, lexeme) in expected {
+            let token = scanner.scan_token();
+            assert_eq!((token.ttype, scanner.lexeme(&token)), (*ttype, *lexeme));
+        }
+        assert_eq!(scanner.scan_token().ttype, TT::EndOfFile);
+    }
+
+    #[rstest]
+    #[case("7 // 2", &[TT::Number])]
+    #[case("(a) // b", &[TT::LeftParen, TT::Identifier, TT::RightParen])]
+    #[case("x; // comment", &[TT::Identifier, TT::Semicolon])]
+    #[case("// comment\n7", &[TT::Number])]
+    #[case("{ // comment", &[TT::LeftBrace])]
+    #[case("xs[0] // 2", &[TT::Identifier, TT::LeftBracket, TT::Number, TT::RightBracket])]
+    #[case("[] // comment", &[TT::LeftBracket, TT::RightBracket])]
+    #[case("2 ** 3 % 4", &[TT::Number, TT::StarStar, TT::Number, TT::Percent, TT::Number])]
+    #[case("a<<b>>c", &[TT::Identifier, TT::LessLess, TT::Identifier, TT::GreaterGreater, TT::Identifier])]
+    #[case("~a&b|c^d", &[TT::Tilde, TT::Identifier, TT::Ampersand, TT::Identifier, TT::Pipe, TT::Identifier, TT::Caret, TT::Identifier])]
+    #[case("<<= >>=", &[TT::LessLess, TT::Assign, TT::GreaterGreater, TT::Assign])]
+    #[case("i += 1", &[TT::Identifier, TT::PlusAssign, TT::Number])]
+    #[case("-= *= /= %=", &[TT::MinusAssign, TT::StarAssign, TT::SlashAssign, TT::PercentAssign])]
+    #[case("++i--", &[TT::PlusPlus, TT::Identifier, TT::MinusMinus])]
+    #[case("a+++b", &[TT::Identifier, TT::PlusPlus, TT::Plus, TT::Identifier])]
+    #[case("x /= 2 // 1", &[TT::Identifier, TT::SlashAssign, TT::Number])]
+    #[case("0..n", &[TT::Number, TT::DotDot, TT::Identifier])]
+    #[case("1..=2.5", &[TT::Number, TT::DotDotEquals, TT::Number])]
+    #[case("a.b", &[TT::Identifier, TT::Dot, TT::Identifier])]
+    #[case("...rest", &[TT::DotDotDot, TT::Identifier])]
+    #[case("(a) => a", &[TT::LeftParen, TT::Identifier, TT::RightParen, TT::Arrow, TT::Identifier])]
+    #[case("a==>b", &[TT::Identifier, TT::Equals, TT::Greater, TT::Identifier])]
+    fn test_operator_tokens(#[case] source: &str, #[case] expected: &[TT]) {
+        let mut scanner = Scanner::new(source);
+
+        for ttype in expected {
+            assert_eq!(scanner.scan_token().ttype, *ttype);
+        }
+        assert_eq!(scanner.scan_token().ttype, TT::EndOfFile);
+    }
+
+    #[rstest]
+    #[case("// 2", &[TT::SlashSlash, TT::Number])]
+    #[case("//2 // 3", &[TT::SlashSlash, TT::Number])]
+    #[case("/// doc", &[])]
+    #[case(" /* c */ // 2", &[TT::SlashSlash, TT::Number])]
+    #[case("\n// 2", &[])]
+    fn test_slash_slash_when_division_is_allowed(#[case] source: &str, #[case] expected: &[TT]) {
+        let mut scanner = Scanner::new(source);
+        scanner.allow_integer_division(true);
+        let first = scanner.scan_token();
+        scanner.allow_integer_division(false);
+
+        let scanned: Vec<TT> = std::iter::once(first)
+            .chain(std::iter::repeat_with(|| scanner.scan_token()))
+            .map(|token| token.ttype)
+            .take_while(|ttype| *ttype != TT::EndOfFile)
+            .collect();
+        assert_eq!(scanned, expected);
+    }
+
+    #[rstest]
+    fn test_token_spans_and_columns() {
+        let source = "var x = 1;\n  print \"é\" + x;";
+        let mut scanner = Scanner::new(source);
+        let expected = [
+            (TT::Var, 0..3, 1, 1),
+            (TT::Identifier, 4..5, 1, 5),
+            (TT::Assign, 6..7, 1, 7),
+            (TT::Number, 8..9, 1, 9),
+            (TT::Semicolon, 9..10, 1, 10),
+            (TT::Print, 13..18, 2, 3),
+            (TT::String, 19..23, 2, 9),
+            (TT::Plus, 24..25, 2, 14),
+            (TT::Identifier, 26..27, 2, 16),
+        ];
+
+        for (ttype, span, line, column) in expected {
+            let token = scanner.scan_token();
+            assert_eq!(
+                (token.ttype, token.span, token.line, token.column),
+                (ttype, span, line, column)
+            );
+        }
+    }
+
+    #[rstest]
+    #[case("café", "café")]
+    #[case("naïve_2 rest", "naïve_2")]
+    #[case("日本語", "日本語")]
+    #[case("ζ", "ζ")]
+    fn test_unicode_identifiers(#[case] source: &str, #[case] lexeme: &str) {
+        let mut scanner = Scanner::new(source);
+        let token = scanner.scan_token();
+
+        assert_eq!(token.ttype, TT::Identifier);
+        assert_eq!(scanner.lexeme(&token), lexeme);
+    }
+
+    #[rstest]
+    fn test_unexpected_unicode_character() {
+        let mut scanner = Scanner::new("a → b");
+
+        assert_eq!(scanner.scan_token().ttype, TT::Identifier);
+        let error = scanner.scan_token();
+        assert_eq!(error.ttype, TT::Error);
+        assert_eq!(error.message(), "Unexpected character '→'");
+        assert_eq!(scanner.lexeme(&error), "→");
+        assert_eq!(scanner.scan_token().ttype, TT::Identifier);
+    }
+
+    #[rstest]
+    fn test_invalid_escape_column() {
+        let token = Scanner::new("\"ab\\qc\"").scan_token();
+
+        assert_eq!(token.ttype, TT::Error);
+        assert_eq!((token.line, token.column), (1, 4));
+    }
+
+    /// Random identifiers, biased towards near-misses of keywords: prefixes,
+    /// extensions and single-character edits.
+    fn random_identifier(rng: &mut Rng) -> String {
+        const CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789_";
+        let keyword = KEYWORDS[rng.below(KEYWORDS.len())].0;
+        let mut identifier = match rng.below(4) {
+            0 => keyword[..rng.below(keyword.len() + 1)].to_string(),
+            1 => keyword.to_string(),
+            2 => {
+                let mut bytes = keyword.as_bytes().to_vec();
+                let index = rng.below(bytes.len());
+                bytes[index] = CHARS[rng.below(CHARS.len())];
+                String::from_utf8(bytes).unwrap()
+            }
+            _ => String::new(),
+        };
+        for _ in 0..rng.below(3) {
+            identifier.push(CHARS[rng.below(CHARS.len())] as char);
+        }
+        if identifier.is_empty() || identifier.as_bytes()[0].is_ascii_digit() {
+            identifier.insert(0, '_');
+        }
+        identifier
+    }
+
+    #[rstest]
+    fn test_keyword_recognition_property() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+
+        for _ in 0..10_000 {
+            let identifier = random_identifier(&mut rng);
+            let expected = KEYWORDS
+                .iter()
+                .find(|(keyword, _)| *keyword == identifier)
+                .map_or(TT::Identifier, |(_, ttype)| *ttype);
+
+            let mut scanner = Scanner::new(&identifier);
+            let token = scanner.scan_token();
+
+            assert_eq!(token.ttype, expected, "scanning '{identifier}'");
+            assert_eq!(scanner.lexeme(&token), identifier);
+            assert_eq!(scanner.scan_token().ttype, TT::EndOfFile);
+        }
     }
 
     #[rstest]
@@ -1,4 +1,5 @@
 use std::fmt::Display;
+use std::ops::Range;
 
 #[derive(Clone, PartialEq, Debug)]
 pub enum Literal {
@@ -29,6 +30,8 @@ pub enum TokenType {
     Minus,
     LeftParen,
     RightParen,
+    LeftBrace,
+    RightBrace,
     LeftBracket,
     RightBracket,
     Semicolon,
@@ -36,6 +39,13 @@ pub enum TokenType {
     Dot,
     Slash,
     Star,
+    Question,
+    Colon,
+    Percent,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
     //double letter tokens
     Bang,
     BangEquals,
@@ -45,11 +55,28 @@ pub enum TokenType {
     GreaterEquals,
     Less,
     LessEquals,
+    StarStar,
+    SlashSlash,
+    LessLess,
+    GreaterGreater,
+    DotDot,
+    DotDotEquals,
+    DotDotDot,
+    PlusAssign,
+    MinusAssign,
+    StarAssign,
+    SlashAssign,
+    PercentAssign,
+    PlusPlus,
+    MinusMinus,
+    Arrow,
     //literals
     String,
+    Interpolation,
     Number,
     Identifier,
-    Constant,
+    /// `true`, `false` and `nil`.
+    Literal,
 
     //keywords
     And,
@@ -65,68 +92,225 @@ pub enum TokenType {
     Var,
     While,
     Break,
+    In,
+    Throw,
+    Try,
+    Catch,
+    Finally,
+    Const,
+    Import,
+    From,
+    As,
     If,
 }
 
-impl Display for TT {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl TokenType {
+    /// Number of token types, the size of tables indexed by token type.
+    pub const COUNT: usize = TT::If as usize + 1;
+
+    /// Whether an operand can end with a token of this type, so that a `//` after
+    /// it inside an expression is integer division.
+    pub fn ends_operand(self) -> bool {
+        matches!(
+            self,
+            TT::Number
+                | TT::String
+                | TT::Identifier
+                | TT::Literal
+                | TT::This
+                | TT::RightParen
+                | TT::RightBracket
+        )
+    }
+
+    /// Stable name of the token type, as printed by the token dump.
+    pub fn name(&self) -> &'static str {
         match self {
-            TT::EndOfFile => write!(f, "EndOfFile"),
-            TT::Plus => write!(f, "Plus"),
-            TT::Minus => write!(f, "Minus"),
-            TT::LeftParen => write!(f, "LeftParen"),
-            TT::RightParen => write!(f, "RightParen"),
-            TT::LeftBracket => write!(f, "LeftBracket"),
-            TT::RightBracket => write!(f, "RightBracket"),
-            TT::Comma => write!(f, ","),
-            TT::Dot => write!(f, "."),
-            TT::Semicolon => write!(f, ";"),
-            TT::Slash => write!(f, "/"),
-            TT::Star => write!(f, "*"),
-            TT::Bang => write!(f, "Bang"),
-            TT::BangEquals => write!(f, "BangEquals"),
-            TT::Equals => write!(f, "Equals"),
-            TT::Assign => write!(f, "Assign"),
-            TT::Greater => write!(f, "Greater"),
-            TT::String => write!(f, "String"),
-            TT::Number => write!(f, "Number"),
-            TT::Constant => write!(f, "Constant"),
-            TT::Identifier => write!(f, "Identifier"),
-            TT::And => write!(f, "And"),
-            TT::If => write!(f, "If"),
-            TT::Break => write!(f, "Break"),
-            _ => write!(f, ""),
+            TT::EndOfFile => "EOF",
+            TT::Error => "ERROR",
+            TT::Undefined => "UNDEFINED",
+            TT::Plus => "PLUS",
+            TT::Minus => "MINUS",
+            TT::LeftParen => "LEFT_PAREN",
+            TT::RightParen => "RIGHT_PAREN",
+            TT::LeftBrace => "LEFT_BRACE",
+            TT::RightBrace => "RIGHT_BRACE",
+            TT::LeftBracket => "LEFT_BRACKET",
+            TT::RightBracket => "RIGHT_BRACKET",
+            TT::Semicolon => "SEMICOLON",
+            TT::Comma => "COMMA",
+            TT::Dot => "DOT",
+            TT::Slash => "SLASH",
+            TT::Star => "STAR",
+            TT::Question => "QUESTION",
+            TT::Colon => "COLON",
+            TT::Percent => "PERCENT",
+            TT::Ampersand => "AMPERSAND",
+            TT::Pipe => "PIPE",
+            TT::Caret => "CARET",
+            TT::Tilde => "TILDE",
+            TT::Bang => "BANG",
+            TT::BangEquals => "BANG_EQUAL",
+            TT::Equals => "EQUAL_EQUAL",
+            TT::Assign => "EQUAL",
+            TT::Greater => "GREATER",
+            TT::GreaterEquals => "GREATER_EQUAL",
+            TT::Less => "LESS",
+            TT::LessEquals => "LESS_EQUAL",
+            TT::StarStar => "STAR_STAR",
+            TT::SlashSlash => "SLASH_SLASH",
+            TT::LessLess => "LESS_LESS",
+            TT::GreaterGreater => "GREATER_GREATER",
+            TT::DotDot => "DOT_DOT",
+            TT::DotDotEquals => "DOT_DOT_EQUAL",
+            TT::DotDotDot => "DOT_DOT_DOT",
+            TT::PlusAssign => "PLUS_EQUAL",
+            TT::MinusAssign => "MINUS_EQUAL",
+            TT::StarAssign => "STAR_EQUAL",
+            TT::SlashAssign => "SLASH_EQUAL",
+            TT::PercentAssign => "PERCENT_EQUAL",
+            TT::PlusPlus => "PLUS_PLUS",
+            TT::MinusMinus => "MINUS_MINUS",
+            TT::Arrow => "ARROW",
+            TT::String => "STRING",
+            TT::Interpolation => "INTERPOLATION",
+            TT::Number => "NUMBER",
+            TT::Identifier => "IDENTIFIER",
+            TT::Literal => "LITERAL",
+            TT::And => "AND",
+            TT::Class => "CLASS",
+            TT::Else => "ELSE",
+            TT::Fun => "FUN",
+            TT::For => "FOR",
+            TT::Or => "OR",
+            TT::Print => "PRINT",
+            TT::Return => "RETURN",
+            TT::Super => "SUPER",
+            TT::This => "THIS",
+            TT::Var => "VAR",
+            TT::While => "WHILE",
+            TT::Break => "BREAK",
+            TT::In => "IN",
+            TT::Throw => "THROW",
+            TT::Try => "TRY",
+            TT::Catch => "CATCH",
+            TT::Finally => "FINALLY",
+            TT::Const => "CONST",
+            TT::Import => "IMPORT",
+            TT::From => "FROM",
+            TT::As => "AS",
+            TT::If => "IF",
         }
     }
 }
+
+impl Display for TT {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+/// Every reserved word with the token type it is scanned as, sorted by keyword.
+/// The scanner, the REPL and the documentation all read this table.
+pub const KEYWORDS: [(&str, TokenType); 26] = [
+    ("and", TT::And),
+    ("as", TT::As),
+    ("break", TT::Break),
+    ("catch", TT::Catch),
+    ("class", TT::Class),
+    ("const", TT::Const),
+    ("else", TT::Else),
+    ("false", TT::Literal),
+    ("finally", TT::Finally),
+    ("for", TT::For),
+    ("from", TT::From),
+    ("fun", TT::Fun),
+    ("if", TT::If),
+    ("import", TT::Import),
+    ("in", TT::In),
+    ("nil", TT::Literal),
+    ("or", TT::Or),
+    ("print", TT::Print),
+    ("return", TT::Return),
+    ("super", TT::Super),
+    ("this", TT::This),
+    ("throw", TT::Throw),
+    ("true", TT::Literal),
+    ("try", TT::Try),
+    ("var", TT::Var),
+    ("while", TT::While),
+];
+
+/// The token type of `identifier` if it is exactly a keyword.
+pub fn keyword(identifier: &str) -> Option<TokenType> {
+    KEYWORDS
+        .binary_search_by(|(keyword, _)| (*keyword).cmp(identifier))
+        .ok()
+        .map(|index| KEYWORDS[index].1)
+}
+
+/// Keywords starting with `prefix`, in alphabetical order.
+pub fn keywords_with_prefix(prefix: &str) -> impl Iterator<Item = &'static str> + '_ {
+    KEYWORDS
+        .iter()
+        .map(|(keyword, _)| *keyword)
+        .filter(move |keyword| keyword.starts_with(prefix))
+}
+
+/// Source text skipped by the scanner but kept for tooling: `///` doc comments.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Trivia {
+    pub line: usize,
+    /// Byte range of the comment in the scanned source.
+    pub span: Range<usize>,
+}
+
 #[derive(Clone, PartialEq, Debug)]
 pub struct Token {
     pub ttype: TT,
+    /// Byte range of the lexeme in the scanned source.
+    pub span: Range<usize>,
     pub line: usize,
-    pub lexeme: String,
+    /// 1-based byte offset of the token within its line.
+    pub column: usize,
+    /// Value of literal tokens, the message of `Error` tokens.
     pub literal: Option<Literal>,
 }
 
 impl Token {
-    pub fn new(ttype: TT, line: usize, lexeme: String) -> Self {
-        Self {
-            ttype,
-            line,
-            lexeme,
-            literal: None,
+    /// The message of an `Error` token, empty for other tokens.
+    pub fn message(&self) -> &str {
+        match (self.ttype, &self.literal) {
+            (TT::Error, Some(Literal::String(message))) => message,
+            _ => "",
         }
     }
+
+    /// Formats the token as `TYPE lexeme literal`, the format used by the scanner
+    /// tests of the upstream Lox test suite, e.g. `NUMBER 123 123.0` or `EOF  null`.
+    /// `source` is the text the token was scanned from.
+    pub fn dump(&self, source: &str) -> String {
+        let name = match (self.ttype, &self.literal) {
+            (TT::Literal, Some(Literal::Boolean(true))) => "TRUE",
+            (TT::Literal, Some(Literal::Boolean(false))) => "FALSE",
+            (TT::Literal, Some(Literal::Nil)) => "NIL",
+            (ttype, _) => ttype.name(),
+        };
+        let literal = match (self.ttype, &self.literal) {
+            (TT::String | TT::Interpolation, Some(Literal::String(s))) => s.clone(),
+            (TT::Number, Some(Literal::Number(n))) => format!("{n:?}"),
+            _ => "null".to_string(),
+        };
+        format!("{} {} {}", name, &source[self.span.clone()], literal)
+    }
 }
 
 impl Display for Token {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self.ttype {
-            TT::String | TT::Number | TT::Constant => {
-                write!(f, "{}::{}:{:?}", self.line, self.ttype, self.literal)
-            }
-
-            _ => write!(f, "{}::{}:{}", self.line, self.ttype, self.lexeme),
-        }
+        write!(
+            f,
+            "{}:{}::{}:{:?}",
+            self.line, self.column, self.ttype, self.span
+        )
     }
 }
 
@@ -134,11 +318,95 @@ impl Default for Token {
     fn default() -> Self {
         Self {
             ttype: TT::Undefined,
+            span: 0..0,
             line: 0,
-            lexeme: "".to_string(),
+            column: 0,
             literal: None,
         }
     }
 }
 
 pub use TokenType as TT;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    fn test_keywords_are_sorted_and_unique() {
+        assert!(KEYWORDS.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    }
+
+    #[rstest]
+    fn test_readme_lists_the_keywords() {
+        let line = include_str!("../README.md")
+            .lines()
+            .find_map(|line| line.strip_prefix("Reserved words: `"))
+            .expect("README should list the reserved words");
+        let listed: Vec<&str> = line.trim_end_matches("`.").split(' ').collect();
+        let keywords: Vec<&str> = KEYWORDS.iter().map(|(name, _)| *name).collect();
+
+        assert_eq!(listed, keywords);
+    }
+
+    #[rstest]
+    #[case("and", Some(TT::And))]
+    #[case("while", Some(TT::While))]
+    #[case("nil", Some(TT::Literal))]
+    #[case("in", Some(TT::In))]
+    #[case("int", None)]
+    #[case("try", Some(TT::Try))]
+    #[case("throw", Some(TT::Throw))]
+    #[case("catch", Some(TT::Catch))]
+    #[case("const", Some(TT::Const))]
+    #[case("constant", None)]
+    #[case("import", Some(TT::Import))]
+    #[case("as", Some(TT::As))]
+    #[case("ask", None)]
+    #[case("fore", None)]
+    #[case("classy", None)]
+    #[case("nilly", None)]
+    #[case("an", None)]
+    #[case("breakfast", None)]
+    #[case("And", None)]
+    #[case("", None)]
+    fn test_keyword(#[case] identifier: &str, #[case] expected: Option<TT>) {
+        assert_eq!(keyword(identifier), expected);
+    }
+
+    #[rstest]
+    fn test_keywords_with_prefix() {
+        assert_eq!(
+            keywords_with_prefix("f").collect::<Vec<_>>(),
+            vec!["false", "finally", "for", "from", "fun"]
+        );
+        assert_eq!(keywords_with_prefix("").count(), KEYWORDS.len());
+        assert_eq!(keywords_with_prefix("x").count(), 0);
+    }
+
+    #[rstest]
+    #[case(TT::Number, "123", Some(Literal::Number(123.0)), "NUMBER 123 123.0")]
+    #[case(TT::Number, "1.5", Some(Literal::Number(1.5)), "NUMBER 1.5 1.5")]
+    #[case(TT::String, "\"\"", Some(Literal::String("".to_string())), "STRING \"\" ")]
+    #[case(TT::Literal, "nil", Some(Literal::Nil), "NIL nil null")]
+    #[case(TT::Literal, "true", Some(Literal::Boolean(true)), "TRUE true null")]
+    #[case(TT::BangEquals, "!=", None, "BANG_EQUAL != null")]
+    #[case(TT::EndOfFile, "", None, "EOF  null")]
+    fn test_dump(
+        #[case] ttype: TT,
+        #[case] lexeme: &str,
+        #[case] literal: Option<Literal>,
+        #[case] expected: &str,
+    ) {
+        let token = Token {
+            ttype,
+            span: 0..lexeme.len(),
+            line: 1,
+            column: 1,
+            literal,
+        };
+
+        assert_eq!(token.dump(lexeme), expected);
+    }
+}
@@ -1,14 +1,27 @@
-use crate::function::*;
-use std::fmt::Display;
-use std::ops::{Add, Div, Mul, Neg, Sub};
+use crate::{error::RuntimeError, function::*, list::List};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::ops::{Add, Div, Mul, Neg, Rem, Sub};
+use std::rc::Rc;
 
-#[derive(Debug, PartialEq, PartialOrd)]
+/// Bounds of the numbers bitwise operators accept: -2^63 and the largest number
+/// below 2^63.
+const I64_MIN: f64 = i64::MIN as f64;
+const I64_MAX: f64 = 9_223_372_036_854_774_784.0;
+
+#[derive(Debug)]
 pub enum Value {
     Number(f64),
     Boolean(bool),
     Nil,
     Str(String),
-    Func(Function),
+    Func(Rc<Function>),
+    List(List),
+    Map(Map),
+    Range(Range),
+    Module(ModuleRef),
 }
 
 impl Clone for Value {
@@ -19,6 +32,28 @@ impl Clone for Value {
             Value::Nil => Value::Nil,
             Value::Str(s) => Value::Str(s.clone()),
             Value::Func(f) => Value::Func(f.clone()),
+            Value::List(l) => Value::List(l.clone()),
+            Value::Map(m) => Value::Map(m.clone()),
+            Value::Range(r) => Value::Range(*r),
+            Value::Module(m) => Value::Module(m.clone()),
+        }
+    }
+}
+
+/// Functions, like lists and maps, are only equal to themselves.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Nil, Value::Nil) => true,
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Func(a), Value::Func(b)) => Rc::ptr_eq(a, b),
+            (Value::List(a), Value::List(b)) => a == b,
+            (Value::Map(a), Value::Map(b)) => a == b,
+            (Value::Range(a), Value::Range(b)) => a == b,
+            (Value::Module(a), Value::Module(b)) => a == b,
+            _ => false,
         }
     }
 }
@@ -36,67 +71,174 @@ impl Value {
     }
 }
 
+impl Value {
+    /// Orders two numbers, or two strings by Unicode code point. Other pairs, and
+    /// comparisons involving NaN, are unordered.
+    pub fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) => a.partial_cmp(b),
+            (Value::Str(a), Value::Str(b)) => Some(a.cmp(b)),
+            _ => None,
+        }
+    }
+
+    /// The element a `for`-`in` loop visits at `cursor`, with the cursor of the element
+    /// after it, or `None` once the loop is done. Lists and maps (which yield their
+    /// keys) use positions as cursors, strings the byte offset of the next character.
+    /// Elements added while the loop runs are visited too.
+    pub fn next_element(&self, cursor: usize) -> Result<Option<(Value, usize)>, RuntimeError> {
+        let element = match self {
+            Value::List(list) => list.at(cursor).map(|value| (value, cursor + 1)),
+            Value::Map(map) => map.key_at(cursor).map(|key| (key, cursor + 1)),
+            Value::Range(range) => range.at(cursor).map(|n| (n, cursor + 1)),
+            Value::Str(s) => s
+                .get(cursor..)
+                .and_then(|rest| rest.chars().next())
+                .map(|c| (Value::Str(c.to_string()), cursor + c.len_utf8())),
+            _ => return Err(RuntimeError::NotIterable),
+        };
+        Ok(element)
+    }
+
+    /// Whether `self` holds `element`: an element of a list, a key of a map, a
+    /// substring of a string or a number a range steps through.
+    pub fn contains(&self, element: &Value) -> Result<bool, RuntimeError> {
+        match (self, element) {
+            (Value::List(list), _) => Ok(list.contains(element)),
+            (Value::Map(map), _) => Ok(map.contains_key(element)),
+            (Value::Str(s), Value::Str(part)) => Ok(s.contains(part.as_str())),
+            (Value::Str(_), _) => Err(RuntimeError::NotSubstring),
+            (Value::Range(range), _) => Ok(range.contains(element)),
+            _ => Err(RuntimeError::NotContainer),
+        }
+    }
+
+    /// The characters of `s` selected by `range`, counted in characters.
+    pub fn slice_str(s: &str, range: &Range) -> Result<Value, RuntimeError> {
+        let bounds = range.slice_bounds(s.chars().count())?;
+        let slice = s.chars().skip(bounds.start).take(bounds.len()).collect();
+        Ok(Value::Str(slice))
+    }
+
+    /// Division truncated toward zero, the counterpart of `%`:
+    /// `a == (a // b) * b + a % b`.
+    pub fn int_div(self, rhs: Self) -> Result<Value, RuntimeError> {
+        match (self / rhs)? {
+            Value::Number(quotient) => Ok(Value::Number(quotient.trunc())),
+            _ => unreachable!("dividing numbers gives a number"),
+        }
+    }
+
+    pub fn pow(self, rhs: Self) -> Result<Value, RuntimeError> {
+        match (self, rhs) {
+            (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a.powf(b))),
+            _ => Err(RuntimeError::OperandsNotNumbers),
+        }
+    }
+
+    /// The number as a 64-bit integer, for the bitwise operators.
+    pub fn as_integer(&self) -> Result<i64, RuntimeError> {
+        match self {
+            Value::Number(n) if n.fract() != 0.0 => Err(RuntimeError::NotInteger),
+            Value::Number(n) if !(I64_MIN..=I64_MAX).contains(n) => {
+                Err(RuntimeError::IntegerOutOfRange)
+            }
+            Value::Number(n) => Ok(*n as i64),
+            _ => Err(RuntimeError::OperandsNotNumbers),
+        }
+    }
+}
+
 impl Display for Value {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_nested(f, &mut Vec::new())
+    }
+}
+
+impl Value {
+    /// Writes the value inside the lists and maps in `open`, see `List::write_nested`.
+    pub fn write_nested(&self, f: &mut fmt::Formatter<'_>, open: &mut Vec<Value>) -> fmt::Result {
         match self {
             Value::Boolean(v) => write!(f, "{v}"),
             Value::Number(v) => write!(f, "{v}"),
-            Value::Nil => write!(f, "Nil"),
+            Value::Nil => write!(f, "nil"),
             Value::Str(s) => write!(f, "{s}"),
             Value::Func(fu) => write!(f, "fn {}", fu.name),
+            Value::List(list) => list.write_nested(f, open),
+            Value::Map(map) => map.write_nested(f, open),
+            Value::Range(range) => write!(f, "{range}"),
+            Value::Module(module) => write!(f, "module {}", module.name),
         }
     }
 }
 
 impl Add for Value {
-    type Output = Value;
+    type Output = Result<Value, RuntimeError>;
+
     fn add(self, rhs: Self) -> Self::Output {
         match (self, rhs) {
-            (Value::Number(a), Value::Number(b)) => Value::Number(a + b),
-            _ => panic!("Invalid operation!"),
+            (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a + b)),
+            (Value::Str(a), Value::Str(b)) => Ok(Value::Str(a + &b)),
+            _ => Err(RuntimeError::InvalidAddition),
         }
     }
 }
 
 impl Mul for Value {
-    type Output = Value;
+    type Output = Result<Value, RuntimeError>;
 
     fn mul(self, rhs: Self) -> Self::Output {
         match (self, rhs) {
-            (Value::Number(a), Value::Number(b)) => Value::Number(a * b),
-            _ => panic!("Invalid operation!"),
+            (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a * b)),
+            _ => Err(RuntimeError::OperandsNotNumbers),
         }
     }
 }
 
 impl Div for Value {
-    type Output = Value;
+    type Output = Result<Value, RuntimeError>;
+
     fn div(self, rhs: Self) -> Self::Output {
         match (self, rhs) {
-            (Value::Number(a), Value::Number(b)) => Value::Number(a / b),
-            _ => panic!("Invalid operation!"),
+            (Value::Number(_), Value::Number(0.0)) => Err(RuntimeError::DivisionByZero),
+            (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a / b)),
+            _ => Err(RuntimeError::OperandsNotNumbers),
+        }
+    }
+}
+
+/// Truncated remainder: the result has the sign of the dividend, so `-7 % 3` is `-1`
+/// and `7 % -3` is `1`.
+impl Rem for Value {
+    type Output = Result<Value, RuntimeError>;
+
+    fn rem(self, rhs: Self) -> Self::Output {
+        match (self, rhs) {
+            (Value::Number(_), Value::Number(0.0)) => Err(RuntimeError::DivisionByZero),
+            (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a % b)),
+            _ => Err(RuntimeError::OperandsNotNumbers),
         }
     }
 }
 
 impl Sub for Value {
-    type Output = Value;
+    type Output = Result<Value, RuntimeError>;
 
     fn sub(self, rhs: Self) -> Self::Output {
         match (self, rhs) {
-            (Value::Number(a), Value::Number(b)) => Value::Number(a - b),
-            _ => panic!("Invalid operation!"),
+            (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a - b)),
+            _ => Err(RuntimeError::OperandsNotNumbers),
         }
     }
 }
 
 impl Neg for Value {
-    type Output = Value;
+    type Output = Result<Value, RuntimeError>;
 
     fn neg(self) -> Self::Output {
         match self {
-            Value::Number(a) => Value::Number(-a),
-            _ => panic!("Invalid operation!"),
+            Value::Number(a) => Ok(Value::Number(-a)),
+            _ => Err(RuntimeError::OperandNotNumber),
         }
     }
 }
@@ -116,7 +258,353 @@ impl ValueArray {
         count
     }
 
-    pub fn read_at(&self, index: usize) -> Value {
-        self.values[index].clone()
+    pub fn read_at(&self, index: usize) -> Option<Value> {
+        self.values.get(index).cloned()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Value> {
+        self.values.iter()
+    }
+}
+
+/// A module bound by `import "path" as name;`: the index of the module in the VM
+/// and the path it was imported by.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModuleRef {
+    pub index: usize,
+    pub name: Rc<str>,
+}
+
+/// The integer range `start..end`, or `start..=end` when `inclusive`. Ranges are
+/// plain values: loops and slices step through them without building a list.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Range {
+    pub start: i64,
+    pub end: i64,
+    pub inclusive: bool,
+}
+
+impl Range {
+    /// Largest bound, beyond which not every integer is representable as a number.
+    const MAX_BOUND: f64 = 9_007_199_254_740_992.0;
+
+    pub fn new(start: &Value, end: &Value, inclusive: bool) -> Result<Self, RuntimeError> {
+        let bound = |value: &Value| match value {
+            Value::Number(n) if n.fract() == 0.0 && n.abs() <= Self::MAX_BOUND => Ok(*n as i64),
+            _ => Err(RuntimeError::InvalidRange),
+        };
+        Ok(Self {
+            start: bound(start)?,
+            end: bound(end)?,
+            inclusive,
+        })
+    }
+
+    /// The first integer after the range.
+    fn stop(&self) -> i64 {
+        self.end + i64::from(self.inclusive)
+    }
+
+    /// The number at `position`, if the range is that long.
+    pub fn at(&self, position: usize) -> Option<Value> {
+        let n = self.start.checked_add(i64::try_from(position).ok()?)?;
+        (n < self.stop()).then_some(Value::Number(n as f64))
+    }
+
+    pub fn contains(&self, value: &Value) -> bool {
+        match value {
+            Value::Number(n) => {
+                n.fract() == 0.0 && (self.start..self.stop()).contains(&(*n as i64))
+            }
+            _ => false,
+        }
+    }
+
+    /// The positions of a sequence of length `len` the range selects, which must
+    /// all exist.
+    pub fn slice_bounds(&self, len: usize) -> Result<std::ops::Range<usize>, RuntimeError> {
+        let out_of_bounds = || RuntimeError::SliceOutOfBounds {
+            range: self.to_string(),
+            len,
+        };
+        let start = usize::try_from(self.start).map_err(|_| out_of_bounds())?;
+        let stop = usize::try_from(self.stop()).map_err(|_| out_of_bounds())?;
+        if start > stop || stop > len {
+            return Err(out_of_bounds());
+        }
+        Ok(start..stop)
+    }
+}
+
+impl Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let operator = if self.inclusive { "..=" } else { ".." };
+        write!(f, "{}{operator}{}", self.start, self.end)
+    }
+}
+
+/// A value usable as a map key. Numbers are keyed by value: `-0.0` is the same key
+/// as `0.0`, and NaN, which is not equal to itself, can't be a key.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Key {
+    Number(u64),
+    Boolean(bool),
+    Nil,
+    Str(String),
+}
+
+impl TryFrom<&Value> for Key {
+    type Error = RuntimeError;
+
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Number(n) if n.is_nan() => Err(RuntimeError::NanKey),
+            Value::Number(n) if *n == 0.0 => Ok(Key::Number(0f64.to_bits())),
+            Value::Number(n) => Ok(Key::Number(n.to_bits())),
+            Value::Boolean(b) => Ok(Key::Boolean(*b)),
+            Value::Nil => Ok(Key::Nil),
+            Value::Str(s) => Ok(Key::Str(s.clone())),
+            _ => Err(RuntimeError::InvalidKey),
+        }
+    }
+}
+
+impl From<&Key> for Value {
+    fn from(key: &Key) -> Self {
+        match key {
+            Key::Number(bits) => Value::Number(f64::from_bits(*bits)),
+            Key::Boolean(b) => Value::Boolean(*b),
+            Key::Nil => Value::Nil,
+            Key::Str(s) => Value::Str(s.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    /// Keys and values in the order the keys were first inserted.
+    entries: Vec<(Key, Value)>,
+    /// Position of every key in `entries`.
+    positions: HashMap<Key, usize>,
+}
+
+/// A Lox map. Like lists, maps are shared by reference and equal only to themselves.
+/// Entries keep the order in which their keys were first inserted: assigning to an
+/// existing key replaces its value in place.
+#[derive(Debug, Clone, Default)]
+pub struct Map(Rc<RefCell<Entries>>);
+
+impl PartialEq for Map {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Map {
+    pub fn get(&self, key: &Value) -> Result<Value, RuntimeError> {
+        let key = Key::try_from(key)?;
+        let entries = self.0.borrow();
+        match entries.positions.get(&key) {
+            Some(position) => Ok(entries.entries[*position].1.clone()),
+            None => Err(RuntimeError::MissingKey(Value::from(&key).to_string())),
+        }
+    }
+
+    pub fn set(&self, key: &Value, value: Value) -> Result<(), RuntimeError> {
+        let key = Key::try_from(key)?;
+        let mut entries = self.0.borrow_mut();
+        match entries.positions.get(&key) {
+            Some(position) => {
+                let position = *position;
+                entries.entries[position].1 = value;
+            }
+            None => {
+                let position = entries.entries.len();
+                entries.positions.insert(key.clone(), position);
+                entries.entries.push((key, value));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn contains_key(&self, key: &Value) -> bool {
+        Key::try_from(key).is_ok_and(|key| self.0.borrow().positions.contains_key(&key))
+    }
+
+    /// The key of the entry at `position` in insertion order.
+    pub fn key_at(&self, position: usize) -> Option<Value> {
+        let entries = self.0.borrow();
+        entries
+            .entries
+            .get(position)
+            .map(|(key, _)| Value::from(key))
+    }
+
+    /// Writes the entries as `{k: v}`, see `List::write_nested`.
+    pub fn write_nested(&self, f: &mut fmt::Formatter<'_>, open: &mut Vec<Value>) -> fmt::Result {
+        let this = Value::Map(self.clone());
+        if open.contains(&this) {
+            return write!(f, "{{...}}");
+        }
+        open.push(this);
+        write!(f, "{{")?;
+        for (position, (key, value)) in self.0.borrow().entries.iter().enumerate() {
+            if position > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: ", Value::from(key))?;
+            value.write_nested(f, open)?;
+        }
+        open.pop();
+        write!(f, "}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[case(Value::Number(1.0), Value::Number(2.0), Some(Ordering::Less))]
+    #[case(Value::Number(f64::NAN), Value::Number(1.0), None)]
+    #[case(Value::Str("b".to_string()), Value::Str("a".to_string()), Some(Ordering::Greater))]
+    #[case(Value::Str("1".to_string()), Value::Number(1.0), None)]
+    #[case(Value::Boolean(false), Value::Boolean(true), None)]
+    #[case(Value::Nil, Value::Nil, None)]
+    #[case(Value::Func(Function::new("f").into()), Value::Func(Function::new("f").into()), None)]
+    fn test_compare(#[case] a: Value, #[case] b: Value, #[case] expected: Option<Ordering>) {
+        assert_eq!(a.compare(&b), expected);
+    }
+
+    #[rstest]
+    #[case(Value::Number(1.0) + Value::Number(2.0), Ok(Value::Number(3.0)))]
+    #[case(Value::Str("a".to_string()) + Value::Str("b".to_string()), Ok(Value::Str("ab".to_string())))]
+    #[case(Value::Str("a".to_string()) + Value::Number(1.0), Err(RuntimeError::InvalidAddition))]
+    #[case(Value::Nil - Value::Number(1.0), Err(RuntimeError::OperandsNotNumbers))]
+    #[case(Value::Number(1.0) * Value::Boolean(true), Err(RuntimeError::OperandsNotNumbers))]
+    #[case(Value::Number(1.0) / Value::Number(0.0), Err(RuntimeError::DivisionByZero))]
+    #[case(Value::Number(1.0) % Value::Number(-0.0), Err(RuntimeError::DivisionByZero))]
+    #[case(Value::Nil / Value::Number(0.0), Err(RuntimeError::OperandsNotNumbers))]
+    #[case(Value::Number(-7.0).int_div(Value::Number(2.0)), Ok(Value::Number(-3.0)))]
+    #[case(-Value::Str("a".to_string()), Err(RuntimeError::OperandNotNumber))]
+    #[case(Value::Func(Function::new("f").into()).pow(Value::Nil), Err(RuntimeError::OperandsNotNumbers))]
+    fn test_operations_are_fallible(
+        #[case] result: Result<Value, RuntimeError>,
+        #[case] expected: Result<Value, RuntimeError>,
+    ) {
+        assert_eq!(result, expected);
+    }
+
+    #[rstest]
+    #[case(Value::Number(-3.0), Ok(-3))]
+    #[case(Value::Number(1.5), Err(RuntimeError::NotInteger))]
+    #[case(Value::Number(f64::NAN), Err(RuntimeError::NotInteger))]
+    #[case(Value::Number(f64::INFINITY), Err(RuntimeError::NotInteger))]
+    #[case(Value::Number(2f64.powi(63)), Err(RuntimeError::IntegerOutOfRange))]
+    #[case(Value::Number(-(2f64.powi(63))), Ok(i64::MIN))]
+    #[case(Value::Nil, Err(RuntimeError::OperandsNotNumbers))]
+    fn test_as_integer(#[case] value: Value, #[case] expected: Result<i64, RuntimeError>) {
+        assert_eq!(value.as_integer(), expected);
+    }
+
+    #[rstest]
+    #[case(Value::Number(1.0), Value::Number(1.0))]
+    #[case(Value::Number(-0.0), Value::Number(0.0))]
+    #[case(Value::Number(0.0), Value::Number(-0.0))]
+    #[case(Value::Str("k".to_string()), Value::Str("k".to_string()))]
+    #[case(Value::Boolean(true), Value::Boolean(true))]
+    #[case(Value::Nil, Value::Nil)]
+    fn test_equal_keys_share_an_entry(#[case] stored: Value, #[case] looked_up: Value) {
+        let map = Map::default();
+        map.set(&stored, Value::Number(1.0)).unwrap();
+
+        assert_eq!(map.get(&looked_up), Ok(Value::Number(1.0)));
+    }
+
+    #[rstest]
+    #[case(Value::Number(1.0), Value::Str("1".to_string()))]
+    #[case(Value::Number(1.0), Value::Boolean(true))]
+    #[case(Value::Number(0.0), Value::Nil)]
+    #[case(Value::Boolean(false), Value::Nil)]
+    fn test_distinct_keys(#[case] stored: Value, #[case] looked_up: Value) {
+        let map = Map::default();
+        map.set(&stored, Value::Number(1.0)).unwrap();
+
+        assert!(map.get(&looked_up).is_err());
+    }
+
+    #[rstest]
+    #[case(Value::Number(f64::NAN), RuntimeError::NanKey)]
+    #[case(Value::List(List::default()), RuntimeError::InvalidKey)]
+    #[case(Value::Map(Map::default()), RuntimeError::InvalidKey)]
+    #[case(Value::Func(Function::new("f").into()), RuntimeError::InvalidKey)]
+    fn test_invalid_keys(#[case] key: Value, #[case] error: RuntimeError) {
+        let map = Map::default();
+
+        assert_eq!(map.set(&key, Value::Nil), Err(error.clone()));
+        assert_eq!(map.get(&key), Err(error));
+    }
+
+    #[rstest]
+    fn test_entries_keep_insertion_order() {
+        let map = Map::default();
+        for key in ["b", "a", "c"] {
+            map.set(&Value::Str(key.to_string()), Value::Nil).unwrap();
+        }
+        map.set(&Value::Str("a".to_string()), Value::Number(1.0))
+            .unwrap();
+        map.set(&Value::Number(-0.0), Value::Boolean(true)).unwrap();
+
+        assert_eq!(
+            Value::Map(map).to_string(),
+            "{b: nil, a: 1, c: nil, 0: true}"
+        );
+    }
+
+    #[rstest]
+    fn test_display_of_recursive_map() {
+        let map = Map::default();
+        map.set(&Value::Nil, Value::Map(map.clone())).unwrap();
+
+        assert_eq!(Value::Map(map).to_string(), "{nil: {...}}");
+    }
+
+    #[rstest]
+    #[case(Range { start: 0, end: 3, inclusive: false }, &[0.0, 1.0, 2.0])]
+    #[case(Range { start: -2, end: -1, inclusive: true }, &[-2.0, -1.0])]
+    #[case(Range { start: 2, end: 2, inclusive: false }, &[])]
+    #[case(Range { start: 2, end: 0, inclusive: true }, &[])]
+    #[case(
+        Range { start: (1 << 53) - 1, end: 1 << 53, inclusive: true },
+        &[9_007_199_254_740_991.0, 9_007_199_254_740_992.0]
+    )]
+    fn test_range_elements(#[case] range: Range, #[case] expected: &[f64]) {
+        let elements: Vec<Value> = (0..).map_while(|position| range.at(position)).collect();
+        let expected: Vec<Value> = expected.iter().map(|n| Value::Number(*n)).collect();
+
+        assert_eq!(elements, expected);
+        for element in &elements {
+            assert!(range.contains(element));
+        }
+    }
+
+    #[rstest]
+    #[case(Range { start: 1, end: 3, inclusive: false }, 3, Ok(1..3))]
+    #[case(Range { start: 0, end: 2, inclusive: true }, 3, Ok(0..3))]
+    #[case(Range { start: 3, end: 3, inclusive: false }, 3, Ok(3..3))]
+    #[case(Range { start: 0, end: 3, inclusive: true }, 3, Err("0..=3"))]
+    #[case(Range { start: -1, end: 1, inclusive: false }, 3, Err("-1..1"))]
+    #[case(Range { start: 2, end: 1, inclusive: false }, 3, Err("2..1"))]
+    fn test_range_slice_bounds(
+        #[case] range: Range,
+        #[case] len: usize,
+        #[case] expected: Result<std::ops::Range<usize>, &str>,
+    ) {
+        let expected = expected.map_err(|range| RuntimeError::SliceOutOfBounds {
+            range: range.to_string(),
+            len,
+        });
+        assert_eq!(range.slice_bounds(len), expected);
     }
 }
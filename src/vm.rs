@@ -1,8 +1,19 @@
-use crate::{compiler::*, function::*, opcode::*, value::Value};
+use crate::{
+    compiler::*,
+    error::RuntimeError,
+    function::*,
+    list::List,
+    opcode::*,
+    value::{Map, ModuleRef, Range, Value},
+};
+use std::cmp::Ordering;
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
+use std::fs;
 use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 #[derive(thiserror::Error, PartialEq)]
 pub enum InterpretResult {
     //InterpretOK,
@@ -19,10 +30,32 @@ impl Debug for InterpretResult {
     }
 }
 
+/// Maximum depth of nested function calls.
+const FRAMES_MAX: usize = 64;
+
 struct CallFrame {
     func_index: usize,
     ip: usize,
     slot: usize,
+    /// Number of named parameters the call passed an argument for.
+    passed: usize,
+    /// The `try` blocks entered in this call, innermost last.
+    handlers: Vec<Handler>,
+    /// Index of the module whose globals the running function uses.
+    module: usize,
+    /// Whether the call runs the top-level code of a module being imported, which
+    /// evaluates to the module.
+    import: bool,
+}
+
+/// Where a `try` block entered by a call continues when a value is thrown.
+#[derive(Clone, Copy)]
+struct Handler {
+    /// Stack height to restore before pushing the thrown value.
+    base: usize,
+    catch_ip: usize,
+    /// Start of the `finally` block a `return` runs first, if any.
+    finally_ip: Option<usize>,
 }
 
 impl CallFrame {
@@ -35,21 +68,80 @@ impl CallFrame {
     }
 }
 
+/// The global variables of the main script or of a module it imported.
+struct Module {
+    /// The path the module was imported by, or the path of the main script.
+    name: String,
+    /// The canonical path of the file the code was read from, if any.
+    path: Option<PathBuf>,
+    globals: HashMap<String, Value>,
+    /// Names of the globals declared with `const`, kept across interpretations.
+    constants: HashSet<String>,
+    /// The error that stopped the module's top-level code, raised again by every
+    /// later import.
+    failure: Option<RuntimeError>,
+}
+
+impl Module {
+    fn new(name: &str, path: Option<PathBuf>) -> Self {
+        Self {
+            name: name.to_string(),
+            path,
+            globals: HashMap::new(),
+            constants: HashSet::new(),
+            failure: None,
+        }
+    }
+}
+
 pub struct VM {
     stack: Vec<Value>,
-    globals: HashMap<String, Value>,
+    /// The main script first, then every module in the order it was imported.
+    modules: Vec<Module>,
+    /// Index of every module imported so far by the canonical path of its file,
+    /// including modules whose top-level code is running or failed.
+    imports: HashMap<PathBuf, usize>,
+    /// Directories searched for a module not found next to the importing file.
+    search_path: Vec<PathBuf>,
     frames: Vec<CallFrame>,
+    out: Box<dyn Write>,
+    err: Box<dyn Write>,
+    /// Maximum number of instructions a script may execute, unlimited when `None`.
+    step_limit: Option<usize>,
 }
 
 impl VM {
     pub fn new() -> Self {
+        Self::with_output(Box::new(std::io::stdout()), Box::new(std::io::stderr()))
+    }
+
+    /// Creates a VM writing `print` output to `out` and diagnostics to `err`.
+    pub fn with_output(out: Box<dyn Write>, err: Box<dyn Write>) -> Self {
         Self {
             stack: Vec::new(),
-            globals: HashMap::new(),
+            modules: vec![Module::new("<script>", None)],
+            imports: HashMap::new(),
+            search_path: Vec::new(),
             frames: Vec::new(),
+            out,
+            err,
+            step_limit: None,
         }
     }
 
+    /// Stops every following script with a runtime error once it has executed
+    /// `limit` instructions.
+    #[cfg(test)]
+    pub fn set_step_limit(&mut self, limit: Option<usize>) {
+        self.step_limit = limit;
+    }
+
+    /// Sets the directories searched, in order, for imported modules that are not
+    /// found relative to the importing file.
+    pub fn set_search_path(&mut self, search_path: Vec<PathBuf>) {
+        self.search_path = search_path;
+    }
+
     pub fn free(&self) {}
 
     fn current(&mut self) -> &Function {
@@ -65,7 +157,11 @@ impl VM {
     pub fn interpret(&mut self, source: &str) -> Result<(), InterpretResult> {
         // need to reset the chunk here!!!
         let mut compiler: Compiler = Compiler::new();
-        let compiled = compiler.compile(source)?;
+        let compiled = compiler.compile(source);
+        for error in compiler.errors() {
+            writeln!(self.err, "{error}").unwrap();
+        }
+        let compiled = compiled?;
         #[cfg(feature = "debug_print_code")]
         if !compiler.had_error() {
             compiled.disassemble(&mut std::io::stdout());
@@ -74,10 +170,25 @@ impl VM {
             func_index: 0,
             ip: 0,
             slot: 1,
+            passed: 0,
+            handlers: Vec::new(),
+            module: 0,
+            import: false,
         });
-        self.stack.push(Value::Func(compiled.function));
+        self.stack.push(Value::Func(compiled.function.into()));
         self.run()
     }
+
+    /// Interprets `source`, read from the file at `path`. The modules it imports are
+    /// looked up relative to that file.
+    pub fn interpret_file(&mut self, path: &Path, source: &str) -> Result<(), InterpretResult> {
+        let main = &mut self.modules[0];
+        main.name = path.display().to_string();
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        self.imports.insert(path.clone(), 0);
+        main.path = Some(path);
+        self.interpret(source)
+    }
     fn ip(&self) -> usize {
         let frame = self.frames.last().unwrap();
         frame.ip
@@ -87,6 +198,7 @@ impl VM {
     }
 
     fn run(&mut self) -> Result<(), InterpretResult> {
+        let mut steps = 0;
         loop {
             #[cfg(feature = "debug_trace_execution")]
             {
@@ -100,220 +212,688 @@ impl VM {
                 self.current()
                     .disassemble_instruction(ip, &mut std::io::stdout());
             }
-            let instruction = self.read_opcode();
-            match instruction {
-                OpCode::GetGlobal => {
-                    let constant = self.read_constant().clone();
-                    if let Value::Str(name) = constant {
-                        if let Some(v) = self.globals.get(&name) {
-                            self.push(v.clone())
-                        } else {
-                            return self.runtime_error(&format!("Undefined variable {name}"));
-                        }
-                    } else {
-                        panic!("Unable to read constant!");
-                    }
-                }
-                OpCode::GetLocal => {
-                    let slot = self.read_byte() as usize;
-                    let slot_offset = self.current_frame().slot;
-                    self.push(self.stack[slot + slot_offset].clone());
-                }
-                OpCode::SetLocal => {
-                    let slot = self.read_byte() as usize;
-                    let slot_offset = self.current_frame().slot;
-                    self.stack[slot + slot_offset] = self.peek(0).clone();
-                }
-                OpCode::DefineGlobal => {
-                    let constant = self.read_constant().clone();
-                    if let Value::Str(k) = constant {
-                        let v = self.pop();
-                        self.globals.insert(k, v);
-                    } else {
-                        panic!("Not able to read constant from the table!")
+            if self.step_limit.is_some_and(|limit| steps >= limit) {
+                return self.runtime_error(RuntimeError::StepLimit(steps));
+            }
+            steps += 1;
+            match self.execute_instruction() {
+                Ok(true) => return Ok(()),
+                Ok(false) => {}
+                Err(error) => {
+                    if let Err(error) = self.catch(error) {
+                        return self.runtime_error(error);
                     }
                 }
-                OpCode::JumpIfFalse => {
-                    let offset = self.read_short();
-                    if self.peek(0).is_falsy() {
-                        self.current_frame().inc(offset);
-                    }
+            }
+        }
+    }
+
+    /// Executes the instruction at the instruction pointer, returning whether the
+    /// script has finished.
+    fn execute_instruction(&mut self) -> Result<bool, RuntimeError> {
+        let instruction = self.read_opcode()?;
+        match instruction {
+            OpCode::GetGlobal => {
+                let name = self.read_name()?;
+                match self.module().globals.get(&name) {
+                    Some(value) => self.push(value.clone()),
+                    None => return Err(RuntimeError::UndefinedVariable(name)),
                 }
-                OpCode::Jump => {
-                    let offset = self.read_short();
-                    self.current_frame().inc(offset)
+            }
+            OpCode::GetLocal => {
+                let slot = self.read_slot()?;
+                self.push(self.stack[slot].clone());
+            }
+            OpCode::SetLocal => {
+                let slot = self.read_slot()?;
+                self.stack[slot] = self.peek(0)?.clone();
+            }
+            OpCode::DefineGlobal => {
+                let name = self.read_name()?;
+                if self.module().constants.contains(&name) {
+                    return Err(RuntimeError::ConstantRedefinition(name));
                 }
-                OpCode::Loop => {
-                    let offset: usize = self.read_short();
-                    self.current_frame().dec(offset)
+                let value = self.pop()?;
+                self.module_mut().globals.insert(name, value);
+            }
+            OpCode::DefineConstant => {
+                let name = self.read_name()?;
+                if self.module().constants.contains(&name) {
+                    return Err(RuntimeError::ConstantRedefinition(name));
                 }
-
-                OpCode::SetGlobal => {
-                    let constant = self.read_constant().clone();
-                    if let Value::Str(var_name) = constant {
-                        let value = self.peek(0).clone();
-                        if let Entry::Occupied(mut entry) = self.globals.entry(var_name.clone()) {
-                            *entry.get_mut() = value;
-                        } else {
-                            return self
-                                .runtime_error(&format!("Undefined variable '{}'", &var_name));
-                        }
-                    } else {
-                        panic!("Not able to read constant from the table!")
+                let value = self.pop()?;
+                let module = self.module_mut();
+                module.globals.insert(name.clone(), value);
+                module.constants.insert(name);
+            }
+            OpCode::Import => {
+                let name = self.read_name()?;
+                self.import(&name)?;
+            }
+            OpCode::GetProperty => {
+                let name = self.read_name()?;
+                let Value::Module(module) = self.pop()? else {
+                    return Err(RuntimeError::NotModule);
+                };
+                match self.modules[module.index].globals.get(&name) {
+                    Some(value) => self.push(value.clone()),
+                    None => {
+                        return Err(RuntimeError::UndefinedProperty {
+                            module: module.name.to_string(),
+                            name,
+                        })
                     }
                 }
-                OpCode::Return => {
-                    return Ok(());
-                }
-                OpCode::Print => {
-                    println!("{}", self.pop());
-                }
-                OpCode::Pop => {
-                    self.pop();
+            }
+            OpCode::JumpIfFalse => {
+                let offset = self.read_short()?;
+                if self.peek(0)?.is_falsy() {
+                    self.current_frame().inc(offset);
                 }
-                OpCode::Constant => {
-                    let constant = self.read_constant();
-                    self.push(constant.clone());
+            }
+            OpCode::Jump => {
+                let offset = self.read_short()?;
+                self.current_frame().inc(offset)
+            }
+            OpCode::Loop => {
+                let offset = self.read_short()?;
+                if offset > self.ip() {
+                    return Err(RuntimeError::InvalidJump);
                 }
-                OpCode::Negate => {
-                    self.validate_unary()?;
-                    let val = self.pop();
-                    self.push(-val)
+                self.current_frame().dec(offset)
+            }
+            OpCode::SetGlobal => {
+                let name = self.read_name()?;
+                if self.module().constants.contains(&name) {
+                    return Err(RuntimeError::ConstantAssignment(name));
                 }
-                OpCode::Add => {
-                    if self.operands_numbers() {
-                        self.binary_op(|a, b| a + b)
-                    } else if self.operands_strings() {
-                        self.binary_op(|a, b| Value::Str(format!("{a}{b}")))
-                    } else {
-                        self.runtime_error("Both operands have to be string or number!")?
+                let value = self.peek(0)?.clone();
+                match self.module_mut().globals.entry(name) {
+                    Entry::Occupied(mut entry) => *entry.get_mut() = value,
+                    Entry::Vacant(entry) => {
+                        return Err(RuntimeError::UndefinedVariable(entry.into_key()))
                     }
                 }
-                OpCode::Substract => {
-                    self.validate_binary()?;
-                    self.binary_op(|a, b| a - b)
-                }
-                OpCode::Multiply => {
-                    self.validate_binary()?;
-                    self.binary_op(|a, b| a * b)
+            }
+            OpCode::Return => return self.return_from_frame(),
+            OpCode::Throw => return Err(RuntimeError::Thrown(self.pop()?)),
+            OpCode::PushHandler => {
+                let discard = self.read_byte()? as usize;
+                let catch = self.read_short()?;
+                let finally = self.read_short()?;
+                let ip = self.ip();
+                let base = self
+                    .stack
+                    .len()
+                    .checked_sub(discard)
+                    .filter(|base| *base >= self.frame_base())
+                    .ok_or(RuntimeError::StackUnderflow)?;
+                self.current_frame().handlers.push(Handler {
+                    base,
+                    catch_ip: ip + catch,
+                    finally_ip: (finally != 0).then_some(ip + finally),
+                });
+            }
+            OpCode::PopHandler => {
+                self.current_frame()
+                    .handlers
+                    .pop()
+                    .ok_or(RuntimeError::NoHandler)?;
+            }
+            OpCode::EndFinally => {
+                let completion = self.pop()?;
+                let value = self.pop()?;
+                match Completion::try_from(&completion) {
+                    Ok(Completion::Normal) => {}
+                    Ok(Completion::Throw) => return Err(RuntimeError::Thrown(value)),
+                    Ok(Completion::Return) => {
+                        self.push(value);
+                        return self.return_from_frame();
+                    }
+                    Err(()) => return Err(RuntimeError::InvalidCompletion),
                 }
-                OpCode::Divide => {
-                    self.validate_binary()?;
-                    self.divide_op()?
+            }
+            OpCode::JumpIfPassed => {
+                let parameter = self.read_byte()? as usize;
+                let offset = self.read_short()?;
+                if parameter < self.current_frame().passed {
+                    self.current_frame().inc(offset);
                 }
-                OpCode::Nil => self.push(Value::Nil),
-                OpCode::True => self.push(Value::Boolean(true)),
-                OpCode::False => self.push(Value::Boolean(false)),
-                OpCode::Not => {
-                    let value = self.pop();
-                    self.push(Value::Boolean(value.is_falsy()));
+            }
+            OpCode::Call => {
+                let count = self.read_byte()? as usize;
+                self.call(count)?;
+            }
+            OpCode::Print => {
+                let value = self.pop()?;
+                writeln!(self.out, "{value}").unwrap();
+            }
+            OpCode::Pop => {
+                self.pop()?;
+            }
+            OpCode::Constant => {
+                let constant = self.read_constant()?;
+                self.push(constant);
+            }
+            OpCode::Negate => {
+                let value = self.pop()?;
+                self.push((-value)?)
+            }
+            OpCode::Add => self.binary_op(|a, b| a + b)?,
+            OpCode::Substract => self.binary_op(|a, b| a - b)?,
+            OpCode::Multiply => self.binary_op(|a, b| a * b)?,
+            OpCode::Divide => self.binary_op(|a, b| a / b)?,
+            OpCode::Modulo => self.binary_op(|a, b| a % b)?,
+            OpCode::IntDivide => self.binary_op(Value::int_div)?,
+            OpCode::Power => self.binary_op(Value::pow)?,
+            OpCode::BitAnd => self.integer_op(|a, b| a & b)?,
+            OpCode::BitOr => self.integer_op(|a, b| a | b)?,
+            OpCode::BitXor => self.integer_op(|a, b| a ^ b)?,
+            OpCode::ShiftLeft => {
+                self.validate_shift()?;
+                self.integer_op(|a, b| a << b)?
+            }
+            OpCode::ShiftRight => {
+                self.validate_shift()?;
+                self.integer_op(|a, b| a >> b)?
+            }
+            OpCode::BitNot => {
+                let value = self.pop()?;
+                let n = value.as_integer().map_err(|error| match error {
+                    RuntimeError::OperandsNotNumbers => RuntimeError::OperandNotNumber,
+                    error => error,
+                })?;
+                self.push(Value::Number(!n as f64))
+            }
+            OpCode::Stringify => {
+                let value = self.pop()?;
+                match value {
+                    Value::Str(_) => self.push(value),
+                    _ => self.push(Value::Str(value.to_string())),
                 }
-                OpCode::Equal => {
-                    let b = self.pop();
-                    let a = self.pop();
-                    self.push(Value::Boolean(a == b));
+            }
+            OpCode::Nil => self.push(Value::Nil),
+            OpCode::True => self.push(Value::Boolean(true)),
+            OpCode::False => self.push(Value::Boolean(false)),
+            OpCode::Not => {
+                let value = self.pop()?;
+                self.push(Value::Boolean(value.is_falsy()));
+            }
+            OpCode::Equal => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.push(Value::Boolean(a == b));
+            }
+            OpCode::NotEqual => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.push(Value::Boolean(a != b));
+            }
+            OpCode::BuildList => {
+                let count = self.read_byte()? as usize;
+                let start = self
+                    .stack
+                    .len()
+                    .checked_sub(count)
+                    .filter(|start| *start >= self.frame_base())
+                    .ok_or(RuntimeError::StackUnderflow)?;
+                let values = self.stack.split_off(start);
+                self.push(Value::List(List::new(values)));
+            }
+            OpCode::ForIter => {
+                let slot = self.read_slot()?;
+                let offset = self.read_short()?;
+                let cursor = match self.stack.get(slot + 1) {
+                    Some(Value::Number(n)) if *n >= 0.0 && n.fract() == 0.0 => *n as usize,
+                    _ => return Err(RuntimeError::InvalidSlot(slot + 1)),
+                };
+                match self.stack[slot].next_element(cursor)? {
+                    Some((element, next)) => {
+                        self.stack[slot + 1] = Value::Number(next as f64);
+                        self.push(element);
+                    }
+                    None => self.current_frame().inc(offset),
                 }
-                OpCode::Less => {
-                    self.validate_binary()?;
-                    self.binary_op(|a: Value, b: Value| Value::Boolean(a < b));
+            }
+            OpCode::BuildMap => {
+                let count = self.read_byte()? as usize;
+                let start = self
+                    .stack
+                    .len()
+                    .checked_sub(2 * count)
+                    .filter(|start| *start >= self.frame_base())
+                    .ok_or(RuntimeError::StackUnderflow)?;
+                let map = Map::default();
+                for entry in self.stack.split_off(start).chunks(2) {
+                    map.set(&entry[0], entry[1].clone())?;
                 }
-                OpCode::Greater => {
-                    self.validate_binary()?;
-                    self.binary_op(|a: Value, b: Value| Value::Boolean(a > b));
+                self.push(Value::Map(map));
+            }
+            OpCode::IndexGet => {
+                let index = self.pop()?;
+                let value = match (self.pop()?, index) {
+                    (Value::List(list), Value::Range(range)) => Value::List(list.slice(&range)?),
+                    (Value::List(list), index) => list.get(&index)?,
+                    (Value::Map(map), index) => map.get(&index)?,
+                    (Value::Str(s), Value::Range(range)) => Value::slice_str(&s, &range)?,
+                    (Value::Str(_), _) => return Err(RuntimeError::InvalidStringIndex),
+                    _ => return Err(RuntimeError::NotIndexable),
+                };
+                self.push(value);
+            }
+            OpCode::IndexSet => {
+                let value = self.pop()?;
+                let index = self.pop()?;
+                match self.pop()? {
+                    Value::List(list) => list.set(&index, value.clone())?,
+                    Value::Map(map) => map.set(&index, value.clone())?,
+                    _ => return Err(RuntimeError::NotIndexable),
                 }
+                self.push(value);
+            }
+            OpCode::LessEqual => self.comparison_op(Ordering::is_le)?,
+            OpCode::GreaterEqual => self.comparison_op(Ordering::is_ge)?,
+            OpCode::Less => self.comparison_op(Ordering::is_lt)?,
+            OpCode::Range | OpCode::RangeInclusive => {
+                let end = self.pop()?;
+                let start = self.pop()?;
+                let inclusive = instruction == OpCode::RangeInclusive;
+                self.push(Value::Range(Range::new(&start, &end, inclusive)?));
             }
+            OpCode::In => {
+                let container = self.pop()?;
+                let element = self.pop()?;
+                self.push(Value::Boolean(container.contains(&element)?));
+            }
+            OpCode::Greater => self.comparison_op(Ordering::is_gt)?,
         }
+        Ok(false)
     }
 
-    fn validate_unary(&mut self) -> Result<(), InterpretResult> {
-        if !self.peek(0).is_number() {
-            self.runtime_error("Operand must be a number")
+    /// Returns the value on top of the stack from the current function, ends the
+    /// script, or ends the top-level code of an imported module, which returns the
+    /// module. When a `try` block with a `finally` block is active in the function,
+    /// the `finally` block runs first and returns at its end.
+    fn return_from_frame(&mut self) -> Result<bool, RuntimeError> {
+        let top_level = self.frames.len() == 1 || self.current_frame().import;
+        let result = if top_level { Value::Nil } else { self.pop()? };
+        let frame = self.current_frame();
+        let finally = frame
+            .handlers
+            .iter()
+            .rposition(|handler| handler.finally_ip.is_some());
+        if let Some(index) = finally {
+            let handler = frame.handlers[index];
+            frame.handlers.truncate(index);
+            frame.ip = handler.finally_ip.unwrap();
+            self.stack.truncate(handler.base);
+            self.push(result);
+            self.push(Completion::Return.into());
+            return Ok(false);
+        }
+        let frame = self.frames.pop().unwrap();
+        self.stack.truncate(frame.func_index);
+        if self.frames.is_empty() {
+            return Ok(true);
+        }
+        if frame.import {
+            self.push(Value::Module(self.module_ref(frame.module)));
         } else {
-            Ok(())
+            self.push(result);
         }
+        Ok(false)
     }
-    fn validate_binary(&mut self) -> Result<(), InterpretResult> {
-        if !self.operands_numbers() {
-            self.runtime_error("Both operands need to be numbers")
-        } else {
-            Ok(())
+
+    /// Pushes the module imported by `name`. A module imported for the first time is
+    /// compiled and its top-level code runs first, in a call that returns the module.
+    fn import(&mut self, name: &str) -> Result<(), RuntimeError> {
+        let path = self.resolve_module(name)?;
+        // The modules whose top-level code is still running: the script and the
+        // imports in progress, outermost first.
+        let loading: Vec<usize> = self
+            .frames
+            .iter()
+            .enumerate()
+            .filter(|(depth, frame)| *depth == 0 || frame.import)
+            .map(|(_, frame)| frame.module)
+            .collect();
+        let cycle_start = loading
+            .iter()
+            .position(|index| self.modules[*index].path.as_ref() == Some(&path));
+        if let Some(start) = cycle_start {
+            let cycle: Vec<&str> = loading[start..]
+                .iter()
+                .map(|index| self.modules[*index].name.as_str())
+                .chain([name])
+                .collect();
+            return Err(RuntimeError::ImportCycle(cycle.join(" -> ")));
+        }
+        if let Some(&index) = self.imports.get(&path) {
+            if let Some(error) = &self.modules[index].failure {
+                return Err(error.clone());
+            }
+            self.push(Value::Module(self.module_ref(index)));
+            return Ok(());
+        }
+        if self.frames.len() == FRAMES_MAX {
+            return Err(RuntimeError::StackOverflow);
         }
+
+        let source = fs::read_to_string(&path)
+            .map_err(|_| RuntimeError::ModuleNotFound(name.to_string()))?;
+        let index = self.modules.len();
+        let mut compiler = Compiler::for_module(index);
+        let compiled = compiler
+            .compile(&source)
+            .map_err(|_| RuntimeError::ModuleCompileError {
+                module: name.to_string(),
+                errors: compiler.errors(),
+            })?;
+        self.imports.insert(path.clone(), index);
+        self.modules.push(Module::new(name, Some(path)));
+        self.push(Value::Func(compiled.function.into()));
+        let func_index = self.stack.len() - 1;
+        self.frames.push(CallFrame {
+            func_index,
+            ip: 0,
+            slot: func_index + 1,
+            passed: 0,
+            handlers: Vec::new(),
+            module: index,
+            import: true,
+        });
+        Ok(())
+    }
+
+    /// The canonical path of the file imported by `name`, relative to the file of the
+    /// running code or else to a directory of the search path.
+    fn resolve_module(&self, name: &str) -> Result<PathBuf, RuntimeError> {
+        let base = self
+            .module()
+            .path
+            .as_deref()
+            .and_then(Path::parent)
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        std::iter::once(base)
+            .chain(self.search_path.iter().cloned())
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+            .and_then(|path| fs::canonicalize(path).ok())
+            .ok_or_else(|| RuntimeError::ModuleNotFound(name.to_string()))
+    }
+
+    fn module_ref(&self, index: usize) -> ModuleRef {
+        ModuleRef {
+            index,
+            name: Rc::from(self.modules[index].name.as_str()),
+        }
+    }
+
+    /// The module of the running function, whose globals global variables refer to.
+    fn module(&self) -> &Module {
+        &self.modules[self.frames.last().map_or(0, |frame| frame.module)]
     }
 
-    fn operands_numbers(&mut self) -> bool {
-        self.peek(0).is_number() && self.peek(1).is_number()
+    fn module_mut(&mut self) -> &mut Module {
+        let index = self.frames.last().map_or(0, |frame| frame.module);
+        &mut self.modules[index]
     }
 
-    fn operands_strings(&mut self) -> bool {
-        self.peek(0).is_string() && self.peek(1).is_string()
+    /// Continues at the innermost handler of any active call, unwinding the calls
+    /// above it, with the thrown value or an error value `{"message", "line"}` made
+    /// from `error` on the stack. Gives `error` back when no handler is active or
+    /// the error can't be caught.
+    fn catch(&mut self, error: RuntimeError) -> Result<(), RuntimeError> {
+        if !error.is_catchable() {
+            return Err(error);
+        }
+        let Some(depth) = self
+            .frames
+            .iter()
+            .rposition(|frame| !frame.handlers.is_empty())
+        else {
+            return Err(error);
+        };
+        self.fail_imports(depth + 1, &error);
+        let exception = match error {
+            RuntimeError::Thrown(value) => value,
+            error => self.error_value(&error),
+        };
+        self.frames.truncate(depth + 1);
+        let frame = self.current_frame();
+        let handler = frame.handlers.pop().unwrap();
+        frame.ip = handler.catch_ip;
+        self.stack.truncate(handler.base);
+        self.push(exception);
+        Ok(())
     }
 
-    fn divide_op(&mut self) -> Result<(), InterpretResult> {
-        if let Value::Number(divider) = self.peek(0) {
-            if *divider == 0.0 {
-                return self.runtime_error("Cannot divide by 0!");
+    /// Records `error` as the failure of every module whose top-level code runs in
+    /// the calls from `depth` on, which are unwound by it.
+    fn fail_imports(&mut self, depth: usize, error: &RuntimeError) {
+        for frame in &self.frames[depth..] {
+            if frame.import {
+                self.modules[frame.module].failure = Some(error.clone());
             }
         }
-        let b = self.pop();
-        let a = self.pop();
-        self.push(a / b);
+    }
+
+    fn error_value(&self, error: &RuntimeError) -> Value {
+        let line = self
+            .frames
+            .last()
+            .and_then(|frame| self.frame_line(frame))
+            .map_or(Value::Nil, |line| Value::Number(line as f64));
+        let map = Map::default();
+        for (key, value) in [("message", Value::Str(error.to_string())), ("line", line)] {
+            map.set(&Value::Str(key.to_string()), value)
+                .expect("strings are valid keys");
+        }
+        Value::Map(map)
+    }
+
+    /// The line of the instruction the call is executing.
+    fn frame_line(&self, frame: &CallFrame) -> Option<usize> {
+        let Some(Value::Func(function)) = self.stack.get(frame.func_index) else {
+            return None;
+        };
+        frame
+            .ip
+            .checked_sub(1)
+            .and_then(|offset| function.read_line(offset))
+    }
+
+    /// Calls the function below the `count` arguments on top of the stack. Its frame
+    /// starts at the first argument, so parameters are the first locals.
+    fn call(&mut self, count: usize) -> Result<(), RuntimeError> {
+        let callee = self
+            .stack
+            .len()
+            .checked_sub(count + 1)
+            .filter(|callee| *callee >= self.frame_base())
+            .ok_or(RuntimeError::StackUnderflow)?;
+        let Value::Func(function) = &self.stack[callee] else {
+            return Err(RuntimeError::NotCallable);
+        };
+        if !function.accepts(count) {
+            return Err(RuntimeError::ArityMismatch {
+                expected: function.expected_arguments(),
+                got: count,
+            });
+        }
+        if self.frames.len() == FRAMES_MAX {
+            return Err(RuntimeError::StackOverflow);
+        }
+        let (max_arity, variadic) = (function.max_arity as usize, function.variadic);
+        let module = function.module;
+        let rest = variadic.then(|| {
+            let extra = count.saturating_sub(max_arity);
+            self.stack.split_off(self.stack.len() - extra)
+        });
+        // Missing arguments hold nil until the prologue stores their default value.
+        let passed = count.min(max_arity);
+        self.stack
+            .extend(std::iter::repeat_n(Value::Nil, max_arity - passed));
+        if let Some(rest) = rest {
+            self.push(Value::List(List::new(rest)));
+        }
+        self.frames.push(CallFrame {
+            func_index: callee,
+            ip: 0,
+            slot: callee + 1,
+            passed,
+            handlers: Vec::new(),
+            module,
+            import: false,
+        });
         Ok(())
     }
 
-    fn binary_op(&mut self, operation: fn(a: Value, b: Value) -> Value) {
-        let b = self.pop();
-        let a = self.pop();
-        self.stack.push(operation(a, b))
+    fn validate_shift(&mut self) -> Result<(), RuntimeError> {
+        if let Value::Number(amount) = self.peek(0)? {
+            if !(0.0..=63.0).contains(amount) {
+                return Err(RuntimeError::InvalidShift);
+            }
+        }
+        Ok(())
     }
 
-    fn read_opcode(&mut self) -> OpCode {
-        let ip = self.ip();
-        let result = self.current().read(ip);
-        self.current_frame().inc(1);
-        result
+    /// Applies `operation` to the two numbers on top of the stack as 64-bit integers.
+    /// Results beyond 2^53 are rounded to the nearest number.
+    fn integer_op(&mut self, operation: fn(a: i64, b: i64) -> i64) -> Result<(), RuntimeError> {
+        let b = self.peek(0)?.as_integer()?;
+        let a = self.peek(1)?.as_integer()?;
+        self.pop()?;
+        self.pop()?;
+        self.push(Value::Number(operation(a, b) as f64));
+        Ok(())
     }
 
-    fn read_byte(&mut self) -> u8 {
-        let ip = self.ip();
-        let result = self.current().read(ip).into();
-        self.current_frame().inc(1);
-        result
+    /// Pops two numbers or two strings and pushes whether their ordering passes
+    /// `test`. Unordered operands (NaN) compare false.
+    fn comparison_op(&mut self, test: fn(Ordering) -> bool) -> Result<(), RuntimeError> {
+        let b = self.pop()?;
+        let a = self.pop()?;
+        let comparable = (a.is_number() && b.is_number()) || (a.is_string() && b.is_string());
+        if !comparable {
+            return Err(RuntimeError::NotComparable);
+        }
+        self.push(Value::Boolean(a.compare(&b).is_some_and(test)));
+        Ok(())
     }
 
-    fn read_short(&mut self) -> usize {
-        let ip = self.ip();
-        let short = self.current().jump_offset(ip);
-        self.current_frame().inc(2);
-        short
+    fn binary_op(
+        &mut self,
+        operation: fn(a: Value, b: Value) -> Result<Value, RuntimeError>,
+    ) -> Result<(), RuntimeError> {
+        let b = self.pop()?;
+        let a = self.pop()?;
+        self.push(operation(a, b)?);
+        Ok(())
     }
 
-    fn read_constant(&mut self) -> Value {
+    fn read_opcode(&mut self) -> Result<OpCode, RuntimeError> {
+        let byte = self.read_byte()?;
+        OpCode::try_from(byte).map_err(RuntimeError::UnknownOpcode)
+    }
+
+    fn read_byte(&mut self) -> Result<u8, RuntimeError> {
         let ip = self.ip();
-        let index = self.current().read(ip) as usize;
+        let byte = self
+            .current()
+            .read_byte(ip)
+            .ok_or(RuntimeError::EndOfCode)?;
         self.current_frame().inc(1);
-        self.current().read_constant(index)
+        Ok(byte)
     }
 
-    fn runtime_error(&mut self, message: &str) -> Result<(), InterpretResult> {
-        let ip = self.ip();
-        let line = self.current().read_line(ip - 1);
+    fn read_short(&mut self) -> Result<usize, RuntimeError> {
+        let high = self.read_byte()? as usize;
+        let low = self.read_byte()? as usize;
+        Ok((high << 8) | low)
+    }
+
+    fn read_constant(&mut self) -> Result<Value, RuntimeError> {
+        let index = self.read_byte()? as usize;
+        self.current()
+            .read_constant(index)
+            .ok_or(RuntimeError::InvalidConstant(index))
+    }
+
+    /// Reads a constant holding the name of a global variable.
+    fn read_name(&mut self) -> Result<String, RuntimeError> {
+        match self.read_constant()? {
+            Value::Str(name) => Ok(name),
+            _ => Err(RuntimeError::InvalidName),
+        }
+    }
+
+    /// Reads the operand of a local variable instruction as an index into the stack.
+    fn read_slot(&mut self) -> Result<usize, RuntimeError> {
+        let slot = self.read_byte()? as usize + self.current_frame().slot;
+        if slot < self.stack.len() {
+            Ok(slot)
+        } else {
+            Err(RuntimeError::InvalidSlot(slot))
+        }
+    }
 
-        eprintln!("{}", message);
-        eprintln!("[line {}] in script", line);
+    /// Reports `error` followed by the line of every active call, innermost first.
+    fn runtime_error(&mut self, error: RuntimeError) -> Result<(), InterpretResult> {
+        writeln!(self.err, "{error}").unwrap();
+        let innermost = self.frames.len().saturating_sub(1);
+        for (depth, frame) in self.frames.iter().enumerate().rev() {
+            let Some(Value::Func(function)) = self.stack.get(frame.func_index) else {
+                continue;
+            };
+            let line = if depth == innermost {
+                error.thrown_line().or_else(|| self.frame_line(frame))
+            } else {
+                self.frame_line(frame)
+            };
+            let location = if depth == 0 {
+                "script".to_string()
+            } else if frame.import {
+                format!("module {}", self.modules[frame.module].name)
+            } else {
+                format!("{}()", function.name)
+            };
+            match line {
+                Some(line) => writeln!(self.err, "[line {line}] in {location}").unwrap(),
+                None => writeln!(self.err, "in {location}").unwrap(),
+            }
+        }
+        self.fail_imports(0, &error);
         self.reset_stack();
         Err(InterpretResult::RuntimeError)
     }
 
     fn reset_stack(&mut self) {
         self.stack.clear();
+        self.frames.clear();
     }
 
-    fn peek(&self, distance: usize) -> &Value {
-        &self.stack[self.stack.len() - distance - 1]
+    /// Index of the first stack value belonging to the current frame: values below
+    /// it, including the running function, can't be popped.
+    fn frame_base(&self) -> usize {
+        self.frames.last().map_or(0, |frame| frame.func_index + 1)
     }
 
-    fn pop(&mut self) -> Value {
-        self.stack.pop().unwrap()
+    fn peek(&self, distance: usize) -> Result<&Value, RuntimeError> {
+        self.stack
+            .len()
+            .checked_sub(distance + 1)
+            .filter(|index| *index >= self.frame_base())
+            .map(|index| &self.stack[index])
+            .ok_or(RuntimeError::StackUnderflow)
+    }
+
+    fn pop(&mut self) -> Result<Value, RuntimeError> {
+        if self.stack.len() <= self.frame_base() {
+            return Err(RuntimeError::StackUnderflow);
+        }
+        Ok(self.stack.pop().unwrap())
     }
     fn push(&mut self, val: Value) {
         self.stack.push(val)
@@ -323,5 +903,758 @@ impl VM {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::golden::Capture;
+    use crate::rng::Rng;
     use rstest::*;
+    use std::io;
+
+    /// A script of random bytes, mostly valid opcodes so execution gets past the
+    /// first instructions, with a few constants of every kind.
+    fn random_script(rng: &mut Rng) -> Function {
+        let opcodes = (0..=u8::MAX)
+            .filter(|byte| OpCode::try_from(*byte).is_ok())
+            .count();
+        let mut function = Function::new("<script>");
+        for constant in [
+            Value::Number(rng.below(5) as f64 - 2.0),
+            Value::Str("x".to_string()),
+            Value::Boolean(true),
+            Value::Nil,
+        ] {
+            function.make_constant(constant);
+        }
+        for _ in 0..rng.below(64) {
+            let byte = match rng.below(8) {
+                0 => rng.below(256),
+                _ => rng.below(opcodes),
+            };
+            function.emit_byte(byte as u8, 1 + rng.below(3));
+        }
+        function
+    }
+
+    /// Prepares `vm` to run `function` as the script, without compiling anything.
+    fn start(vm: &mut VM, function: Function) {
+        vm.frames.push(CallFrame {
+            func_index: 0,
+            ip: 0,
+            slot: 1,
+            passed: 0,
+            handlers: Vec::new(),
+            module: 0,
+            import: false,
+        });
+        vm.stack.push(Value::Func(function.into()));
+    }
+
+    #[rstest]
+    fn test_random_bytecode_never_panics() {
+        let mut rng = Rng(0x5EED);
+
+        for _ in 0..500 {
+            let mut vm = VM::with_output(Box::new(io::sink()), Box::new(io::sink()));
+            vm.set_step_limit(Some(200));
+            start(&mut vm, random_script(&mut rng));
+            // Values already on the stack, as if declared as locals.
+            for _ in 0..rng.below(6) {
+                vm.stack.push(match rng.below(3) {
+                    0 => Value::Number(rng.below(70) as f64 - 4.0),
+                    1 => Value::Str("s".to_string()),
+                    _ => Value::Boolean(false),
+                });
+            }
+
+            let _ = vm.run();
+        }
+    }
+
+    #[rstest]
+    #[case(&[OpCode::Pop.into()], "Stack underflow")]
+    #[case(&[255], "Unknown opcode 255")]
+    #[case(&[OpCode::Constant.into()], "Unexpected end of bytecode")]
+    #[case(&[OpCode::Constant.into(), 9], "Invalid constant index 9")]
+    #[case(&[OpCode::GetGlobal.into(), 0], "Variable name constant is not a string")]
+    #[case(&[OpCode::GetLocal.into(), 3], "Invalid stack slot 4")]
+    #[case(&[OpCode::Loop.into(), 0, 9], "Jump before the start of the bytecode")]
+    #[case(&[OpCode::Loop.into(), 0, 3], "Step limit exceeded after 10 instructions")]
+    fn test_corrupted_bytecode_is_a_runtime_error(#[case] code: &[u8], #[case] message: &str) {
+        let err = Capture::default();
+        let mut vm = VM::with_output(Box::new(io::sink()), Box::new(err.clone()));
+        vm.set_step_limit(Some(10));
+        let mut function = Function::new("<script>");
+        function.make_constant(Value::Number(1.0));
+        for byte in code {
+            function.emit_byte(*byte, 1);
+        }
+        start(&mut vm, function);
+
+        assert_eq!(vm.run(), Err(InterpretResult::RuntimeError));
+        assert_eq!(err.lines()[0], message);
+    }
+
+    #[rstest]
+    #[case(&[OpCode::Pop.into()], "Stack underflow")]
+    #[case(&[255], "Unknown opcode 255")]
+    #[case(&[OpCode::Constant.into(), 9], "Invalid constant index 9")]
+    #[case(&[OpCode::GetLocal.into(), 3], "Invalid stack slot 4")]
+    #[case(
+        &[OpCode::Nil.into(), OpCode::Nil.into(), OpCode::EndFinally.into()],
+        "Invalid completion at the end of a finally block"
+    )]
+    fn test_corrupted_bytecode_is_not_caught(#[case] code: &[u8], #[case] message: &str) {
+        let err = Capture::default();
+        let mut vm = VM::with_output(Box::new(io::sink()), Box::new(err.clone()));
+        let mut function = Function::new("<script>");
+        function.make_constant(Value::Number(1.0));
+        // A handler whose catch block, right after `code`, ends the script.
+        let handler = [OpCode::PushHandler.into(), 0, 0, code.len() as u8, 0, 0];
+        let end = [OpCode::Return.into()];
+        for byte in handler.iter().chain(code).chain(&end) {
+            function.emit_byte(*byte, 1);
+        }
+        start(&mut vm, function);
+
+        assert_eq!(vm.run(), Err(InterpretResult::RuntimeError));
+        assert_eq!(err.lines()[0], message);
+    }
+
+    #[rstest]
+    fn test_constant_indices_beyond_opcode_range() {
+        let source = (0..40).map(|i| format!("print {i};")).collect::<String>();
+        let mut vm = VM::new();
+
+        assert_eq!(vm.interpret(&source), Ok(()));
+    }
+
+    #[rstest]
+    #[case("print 7 % 3;", "1")]
+    #[case("print -7 % 3;", "-1")]
+    #[case("print 7 % -3;", "1")]
+    #[case("print 5.5 % 2;", "1.5")]
+    #[case("print 7 // 2;", "3")]
+    #[case("print -7 // 2;", "-3")]
+    #[case("print 7.9 // 1;", "7")]
+    #[case("print 2 ** 10;", "1024")]
+    #[case("print 2 ** 3 ** 2;", "512")]
+    #[case("print -2 ** 2;", "-4")]
+    #[case("print 2 ** -1;", "0.5")]
+    #[case("print 1 + 2 * 3 ** 2 % 4;", "3")]
+    #[case("print 10 - 7 // 2 * 2;", "4")]
+    #[case("print 10 - 3 - 2;", "5")]
+    fn test_arithmetic_operators(#[case] source: &str, #[case] expected: &str) {
+        let outcome = crate::golden::interpret(source);
+
+        assert_eq!(outcome.result, Ok(()));
+        assert_eq!(outcome.output, vec![expected]);
+    }
+
+    #[rstest]
+    #[case("print 1 % 0;", "Cannot divide by 0!")]
+    #[case("print 1 // 0;", "Cannot divide by 0!")]
+    #[case("print \"a\" % 2;", "Both operands need to be numbers")]
+    #[case("print 1 // nil;", "Both operands need to be numbers")]
+    #[case("print true ** 2;", "Both operands need to be numbers")]
+    fn test_arithmetic_runtime_errors(#[case] source: &str, #[case] message: &str) {
+        let outcome = crate::golden::interpret(source);
+
+        assert_eq!(outcome.result, Err(InterpretResult::RuntimeError));
+        assert_eq!(outcome.errors, vec![message, "[line 1] in script"]);
+    }
+
+    #[rstest]
+    #[case("print 12 & 10;", "8")]
+    #[case("print 12 | 3;", "15")]
+    #[case("print 12 ^ 10;", "6")]
+    #[case("print ~0;", "-1")]
+    #[case("print ~5;", "-6")]
+    #[case("print 1 << 10;", "1024")]
+    #[case("print -16 >> 2;", "-4")]
+    #[case("print 255 >> 4 & 3;", "3")]
+    #[case("print 1 | 2 ^ 3 & 4;", "3")]
+    #[case("print 1 << 2 + 1;", "8")]
+    #[case("print (6 & 3) == 2;", "true")]
+    #[case("print 1 << 63 < 0;", "true")]
+    #[case("print (1 << 62) == 2 ** 62;", "true")]
+    #[case("print 0xFF00 & 0x0FF0;", "3840")]
+    fn test_bitwise_operators(#[case] source: &str, #[case] expected: &str) {
+        let outcome = crate::golden::interpret(source);
+
+        assert_eq!(outcome.result, Ok(()));
+        assert_eq!(outcome.output, vec![expected]);
+    }
+
+    #[rstest]
+    #[case("print 1.5 & 1;", "Bitwise operands must be integers")]
+    #[case("print ~0.5;", "Bitwise operands must be integers")]
+    #[case("print 1 | (-1) ** 0.5;", "Bitwise operands must be integers")]
+    #[case("print 6 & 3 == 2;", "Both operands need to be numbers")]
+    #[case("print 1 ^ 2 ** 64;", "Bitwise operands must fit in 64 bits")]
+    #[case("print 1 << 64;", "Shift amount must be between 0 and 63")]
+    #[case("print 1 >> -1;", "Shift amount must be between 0 and 63")]
+    #[case("print \"a\" & 1;", "Both operands need to be numbers")]
+    #[case("print ~nil;", "Operand must be a number")]
+    fn test_bitwise_runtime_errors(#[case] source: &str, #[case] message: &str) {
+        let outcome = crate::golden::interpret(source);
+
+        assert_eq!(outcome.result, Err(InterpretResult::RuntimeError));
+        assert_eq!(outcome.errors, vec![message, "[line 1] in script"]);
+    }
+
+    #[rstest]
+    #[case("1 <= 1", true)]
+    #[case("1 <= 0", false)]
+    #[case("1 >= 1", true)]
+    #[case("0 >= 1", false)]
+    #[case("1 != 1", false)]
+    #[case("1 != \"1\"", true)]
+    #[case("nan < 1", false)]
+    #[case("nan > 1", false)]
+    #[case("nan <= 1", false)]
+    #[case("nan >= 1", false)]
+    #[case("1 <= nan", false)]
+    #[case("1 >= nan", false)]
+    #[case("nan <= nan", false)]
+    #[case("nan >= nan", false)]
+    #[case("nan == nan", false)]
+    #[case("nan != nan", true)]
+    #[case("nan != 1", true)]
+    fn test_comparisons_follow_ieee_754(#[case] comparison: &str, #[case] expected: bool) {
+        let source = format!("var nan = (-1) ** 0.5; print {comparison};");
+        let outcome = crate::golden::interpret(&source);
+
+        assert_eq!(outcome.result, Ok(()));
+        assert_eq!(outcome.output, vec![expected.to_string()]);
+    }
+
+    #[rstest]
+    #[case("\"apple\" < \"banana\"", true)]
+    #[case("\"apple\" > \"banana\"", false)]
+    #[case("\"app\" < \"apple\"", true)]
+    #[case("\"\" < \"a\"", true)]
+    #[case("\"Zebra\" < \"apple\"", true)]
+    #[case("\"same\" <= \"same\"", true)]
+    #[case("\"same\" >= \"same\"", true)]
+    #[case("\"z\" < \"é\"", true)]
+    #[case("\"\u{FFFF}\" < \"\u{10000}\"", true)]
+    fn test_string_ordering(#[case] comparison: &str, #[case] expected: bool) {
+        let outcome = crate::golden::interpret(&format!("print {comparison};"));
+
+        assert_eq!(outcome.result, Ok(()));
+        assert_eq!(outcome.output, vec![expected.to_string()]);
+    }
+
+    #[rstest]
+    #[case("print 1 < \"1\";")]
+    #[case("print \"a\" >= nil;")]
+    #[case("print true > false;")]
+    fn test_ordering_mixed_types(#[case] source: &str) {
+        let outcome = crate::golden::interpret(source);
+
+        assert_eq!(outcome.result, Err(InterpretResult::RuntimeError));
+        assert_eq!(
+            outcome.errors[0],
+            "Operands must be two numbers or two strings"
+        );
+    }
+
+    #[rstest]
+    #[case("print [];", "[]")]
+    #[case("print [1, \"two\", [nil, true]];", "[1, two, [nil, true]]")]
+    #[case("print [10, 20, 30][2];", "30")]
+    #[case("var xs = [1, 2]; xs[1] = 5; print xs;", "[1, 5]")]
+    #[case("var xs = [1]; var ys = xs; ys[0] = 2; print xs[0];", "2")]
+    #[case("var xs = [[0]]; xs[0][0] = 1; print xs;", "[[1]]")]
+    #[case("var xs = [0]; print xs[0] = 7;", "7")]
+    #[case("print [1] == [1];", "false")]
+    #[case("var xs = [1]; print xs == xs;", "true")]
+    #[case("print \"${[1, 2]}\";", "[1, 2]")]
+    fn test_lists(#[case] source: &str, #[case] expected: &str) {
+        let outcome = crate::golden::interpret(source);
+
+        assert_eq!(outcome.result, Ok(()));
+        assert_eq!(outcome.output, vec![expected]);
+    }
+
+    #[rstest]
+    #[case("print [1, 2][2];", "List index 2 out of bounds for length 2")]
+    #[case("print [][0];", "List index 0 out of bounds for length 0")]
+    #[case("print [1][-1];", "Negative list index -1")]
+    #[case("var xs = [1]; xs[-2] = 0;", "Negative list index -2")]
+    #[case("var xs = [1]; xs[1] = 0;", "List index 1 out of bounds for length 1")]
+    #[case("print [1][0.5];", "List index must be an integer")]
+    #[case("print [1][\"0\"];", "List index must be an integer")]
+    #[case("print \"abc\"[0];", "Strings can only be indexed by ranges")]
+    #[case("print true[0..1];", "Only lists and maps can be indexed")]
+    #[case("var n = 1; n[0] = 1;", "Only lists and maps can be indexed")]
+    fn test_list_runtime_errors(#[case] source: &str, #[case] message: &str) {
+        let outcome = crate::golden::interpret(source);
+
+        assert_eq!(outcome.result, Err(InterpretResult::RuntimeError));
+        assert_eq!(outcome.errors, vec![message, "[line 1] in script"]);
+    }
+
+    #[rstest]
+    #[case("print {};", "{}")]
+    #[case("print {\"a\": 1, 2: [true], nil: {}};", "{a: 1, 2: [true], nil: {}}")]
+    #[case("print {\"a\": 1, \"b\": 2,}[\"b\"];", "2")]
+    #[case(
+        "var m = {\"b\": 1}; m[\"a\"] = 2; m[\"b\"] = 3; print m;",
+        "{b: 3, a: 2}"
+    )]
+    #[case("var m = {0: \"zero\"}; print m[-0];", "zero")]
+    #[case(
+        "var m = {1: \"n\", \"1\": \"s\", true: \"b\"}; print m[1] + m[\"1\"] + m[true];",
+        "nsb"
+    )]
+    #[case("var m = {1: 0, 1: 2}; print m;", "{1: 2}")]
+    #[case("var m = {}; var n = m; n[1] = 1; print m;", "{1: 1}")]
+    #[case("print {} == {};", "false")]
+    #[case("if (true) { print {1: 2}[1]; }", "2")]
+    fn test_maps(#[case] source: &str, #[case] expected: &str) {
+        let outcome = crate::golden::interpret(source);
+
+        assert_eq!(outcome.result, Ok(()));
+        assert_eq!(outcome.output, vec![expected]);
+    }
+
+    #[rstest]
+    #[case("print {\"a\": 1}[\"b\"];", "Key b not found in map")]
+    #[case("print {}[10 ** 400 - 10 ** 400];", "NaN can't be used as a map key")]
+    #[case(
+        "var m = {}; m[10 ** 400 - 10 ** 400] = 1;",
+        "NaN can't be used as a map key"
+    )]
+    #[case("print {[]: 1};", "Map keys must be strings, numbers, booleans or nil")]
+    #[case(
+        "var m = {}; m[m] = 1;",
+        "Map keys must be strings, numbers, booleans or nil"
+    )]
+    fn test_map_runtime_errors(#[case] source: &str, #[case] message: &str) {
+        let outcome = crate::golden::interpret(source);
+
+        assert_eq!(outcome.result, Err(InterpretResult::RuntimeError));
+        assert_eq!(outcome.errors, vec![message, "[line 1] in script"]);
+    }
+
+    #[rstest]
+    #[case("for (var x in [1, 2, 3]) s = s + \"${x}\";", "123")]
+    #[case("for (var x in []) s = \"never\";", "")]
+    #[case("for (var k in {\"b\": 1, \"a\": 2, 3: 4}) s = s + \"${k}\";", "ba3")]
+    #[case("for (var c in \"héllo\") s = c + s;", "olléh")]
+    #[case(
+        "var xs = [1]; for (var x in xs) if (x < 3) xs[0] = x + 1; s = \"${xs}\";",
+        "[2]"
+    )]
+    #[case("for (var x in [[1], [2]]) for (var y in x) s = s + \"${y}\";", "12")]
+    #[case("var x = \"ab\"; for (var x in x) s = s + x; s = s + x;", "abab")]
+    #[case(
+        "{ var n = 1; for (var x in [2, 3]) { var y = x * n; s = s + \"${y}\"; } }",
+        "23"
+    )]
+    fn test_for_in(#[case] source: &str, #[case] expected: &str) {
+        let outcome = crate::golden::interpret(&format!("var s = \"\"; {source} print s;"));
+
+        assert_eq!(outcome.result, Ok(()));
+        assert_eq!(outcome.output, vec![expected]);
+    }
+
+    #[rstest]
+    #[case("for (var x in 5) print x;")]
+    #[case("for (var x in nil) print x;")]
+    #[case("var m = {}; for (var x in m[1] = true) print x;")]
+    fn test_for_in_over_non_iterable(#[case] source: &str) {
+        let outcome = crate::golden::interpret(source);
+
+        assert_eq!(outcome.result, Err(InterpretResult::RuntimeError));
+        assert_eq!(
+            outcome.errors,
+            vec![
+                "Can only iterate over lists, maps, strings and ranges",
+                "[line 1] in script"
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case("print 1..4;", "1..4")]
+    #[case("print -1..=2 * 3;", "-1..=6")]
+    #[case("print 1..3 == 1..3;", "true")]
+    #[case("print 1..3 == 1..=3;", "false")]
+    #[case("var s = \"\"; for (var i in 2..5) s = s + \"${i}\"; print s;", "234")]
+    #[case(
+        "var s = \"\"; for (var i in -1..=1) s = s + \"${i}\"; print s;",
+        "-101"
+    )]
+    #[case("var s = \"\"; for (var i in 3..1) s = \"never\"; print s;", "")]
+    #[case("print [1, 2, 3, 4][1..3];", "[2, 3]")]
+    #[case("print [1, 2, 3][0..=2];", "[1, 2, 3]")]
+    #[case("print [1, 2, 3][3..3];", "[]")]
+    #[case("var xs = [[1]]; var ys = xs[0..1]; ys[0][0] = 2; print xs;", "[[2]]")]
+    #[case("var xs = [1]; var ys = xs[0..1]; ys[0] = 2; print xs;", "[1]")]
+    #[case("print \"héllo\"[1..=3];", "éll")]
+    #[case("print 2 in 1..3;", "true")]
+    #[case("print 3 in 1..3;", "false")]
+    #[case("print 3 in 1..=3;", "true")]
+    #[case("print 1.5 in 1..3;", "false")]
+    #[case("print \"1\" in 1..3;", "false")]
+    #[case("print 1 + 1 in [1, 2];", "true")]
+    #[case("print [1] in [[1]];", "false")]
+    #[case("print nil in {nil: 1};", "true")]
+    #[case("print [] in {};", "false")]
+    #[case("print \"ell\" in \"hello\";", "true")]
+    #[case("print \"\" in \"\";", "true")]
+    fn test_ranges(#[case] source: &str, #[case] expected: &str) {
+        let outcome = crate::golden::interpret(source);
+
+        assert_eq!(outcome.result, Ok(()));
+        assert_eq!(outcome.output, vec![expected]);
+    }
+
+    #[rstest]
+    #[case("print 0.5..2;", "Range bounds must be integers")]
+    #[case("print 1..\"2\";", "Range bounds must be integers")]
+    #[case("print 0..10 ** 16;", "Range bounds must be integers")]
+    #[case("print [1, 2][1..3];", "Slice 1..3 out of bounds for length 2")]
+    #[case("print [1, 2][-1..1];", "Slice -1..1 out of bounds for length 2")]
+    #[case("print [1, 2][2..0];", "Slice 2..0 out of bounds for length 2")]
+    #[case("print \"ab\"[0..=2];", "Slice 0..=2 out of bounds for length 2")]
+    #[case("print 1 in \"1\";", "Only strings can be searched for in a string")]
+    #[case(
+        "print 1 in 1;",
+        "Can only test membership in lists, maps, strings and ranges"
+    )]
+    fn test_range_runtime_errors(#[case] source: &str, #[case] message: &str) {
+        let outcome = crate::golden::interpret(source);
+
+        assert_eq!(outcome.result, Err(InterpretResult::RuntimeError));
+        assert_eq!(outcome.errors, vec![message, "[line 1] in script"]);
+    }
+
+    #[rstest]
+    #[case("fun add(a, b) { return a + b; } print add(1, 2);", "3")]
+    #[case("fun f() {} print f();", "nil")]
+    #[case("fun f() { return; } print f();", "nil")]
+    #[case(
+        "fun fib(n) { return n < 2 ? n : fib(n - 1) + fib(n - 2); } print fib(10);",
+        "55"
+    )]
+    #[case("var f = fun (x) { return x * 2; }; print f(4);", "8")]
+    #[case("print fun () { return \"now\"; }();", "now")]
+    #[case("var twice = (f, x) => f(f(x)); print twice((x) => x * 3, 2);", "18")]
+    #[case("print (() => 1)();", "1")]
+    #[case("print ((a) => (b) => b)(1)(2);", "2")]
+    #[case("var xs = [(x) => x + 1]; print xs[0](1);", "2")]
+    #[case(
+        "{ var a = 1; fun f(b) { var c = 3; return b + c; } print a + f(2); }",
+        "6"
+    )]
+    #[case("fun f(n) { for (var x in [n]) return x; } print f(7);", "7")]
+    #[case("print (1 + 2) * 3;", "9")]
+    #[case("fun f() {} print f;", "fn f")]
+    #[case("fun f(a, b = 2) { return [a, b]; } print f(1);", "[1, 2]")]
+    #[case("fun f(a, b = 2) { return [a, b]; } print f(1, nil);", "[1, nil]")]
+    #[case(
+        "fun f(a, b = a + 1, c = b * 2) { return [a, b, c]; } print f(1);",
+        "[1, 2, 4]"
+    )]
+    #[case(
+        "fun f(a, b = a + 1, c = b * 2) { return [a, b, c]; } print f(1, 5);",
+        "[1, 5, 10]"
+    )]
+    #[case("var n = 0; fun f(a = n++) { return a; } f(); f(7); print f();", "1")]
+    #[case("fun f(...xs) { return xs; } print f();", "[]")]
+    #[case("fun f(a, ...xs) { return xs; } print f(1, 2, 3);", "[2, 3]")]
+    #[case("fun f(a = 0, ...xs) { return [a, xs]; } print f();", "[0, []]")]
+    #[case("var f = (x = 10) => x; print f();", "10")]
+    #[case("var f = (...xs) => xs; print f(1, [2]);", "[1, [2]]")]
+    fn test_functions(#[case] source: &str, #[case] expected: &str) {
+        let outcome = crate::golden::interpret(source);
+
+        assert_eq!(outcome.result, Ok(()));
+        assert_eq!(outcome.output, vec![expected]);
+    }
+
+    #[rstest]
+    #[case("var x = 1; x();", "Can only call functions")]
+    #[case("\"f\"();", "Can only call functions")]
+    #[case("((a) => a)();", "Expected 1 argument but got 0")]
+    #[case("fun f() {} f(1, 2);", "Expected 0 arguments but got 2")]
+    #[case("fun f(a, b = 1) {} f();", "Expected 1 to 2 arguments but got 0")]
+    #[case(
+        "fun f(a, b = 1) {} f(1, 2, 3);",
+        "Expected 1 to 2 arguments but got 3"
+    )]
+    #[case("fun f(a, ...r) {} f();", "Expected at least 1 argument but got 0")]
+    #[case(
+        "fun f(a, b, ...r) {} f(1);",
+        "Expected at least 2 arguments but got 1"
+    )]
+    #[case("fun f() { f(); } f();", "Stack overflow")]
+    #[case("fun f() { a = 2; } const a = 1; f();", "Can't assign to constant a")]
+    fn test_call_runtime_errors(#[case] source: &str, #[case] message: &str) {
+        let outcome = crate::golden::interpret(source);
+
+        assert_eq!(outcome.result, Err(InterpretResult::RuntimeError));
+        assert_eq!(outcome.errors[0], message);
+    }
+
+    #[rstest]
+    #[case("try { throw 1; } catch (e) { print e; }", vec!["1"])]
+    #[case("try { print 1; } catch (e) { print 2; } print 3;", vec!["1", "3"])]
+    #[case("try { print 1 / 0; } catch (e) { print e; }", vec!["{message: Cannot divide by 0!, line: 1}"])]
+    #[case("try {\n  x;\n} catch (e) { print e[\"line\"]; }", vec!["2"])]
+    #[case("try { [][0]; } catch (e) { print e[\"message\"]; }", vec!["List index 0 out of bounds for length 0"])]
+    #[case("try { print 1; } finally { print 2; } print 3;", vec!["1", "2", "3"])]
+    #[case(
+        "try { throw 1; } catch (e) { print e; } finally { print 2; }",
+        vec!["1", "2"]
+    )]
+    #[case(
+        "try { try { throw 1; } finally { print 2; } } catch (e) { print e; }",
+        vec!["2", "1"]
+    )]
+    #[case(
+        "try { try { throw 1; } catch (e) { throw e + 1; } finally { print 0; } } catch (e) { print e; }",
+        vec!["0", "2"]
+    )]
+    #[case("fun f() { try { return 1; } finally { print 2; } } print f();", vec!["2", "1"])]
+    #[case("fun f() { try { return 1; } finally { return 2; } } print f();", vec!["2"])]
+    #[case(
+        "fun f() { try { try { return 1; } finally { print 2; } } finally { print 3; } } print f();",
+        vec!["2", "3", "1"]
+    )]
+    #[case(
+        "fun f(n) { if (n == 0) throw \"deep\"; return f(n - 1); } try { f(3); } catch (e) { print e; } print 1;",
+        vec!["deep", "1"]
+    )]
+    #[case(
+        "var a = 1; try { var b = 2; for (var x in [3]) throw x; } catch (e) { var c = 4; print [a, e, c]; }",
+        vec!["[1, 3, 4]"]
+    )]
+    #[case(
+        "for (var i = 0; i < 2; i++) { try { if (i == 0) throw i; print i; } catch (e) { print -1; } }",
+        vec!["-1", "1"]
+    )]
+    fn test_exceptions(#[case] source: &str, #[case] expected: Vec<&str>) {
+        let outcome = crate::golden::interpret(source);
+
+        assert_eq!(outcome.result, Ok(()));
+        assert_eq!(outcome.output, expected);
+    }
+
+    #[rstest]
+    #[case("throw 1;", "Uncaught exception: 1")]
+    #[case("throw {\"message\": \"bad input\"};", "bad input")]
+    #[case("try { throw 1; } catch (e) { throw [e]; }", "Uncaught exception: [1]")]
+    #[case("try { throw 1; } finally { print 2; }", "Uncaught exception: 1")]
+    #[case("fun f() { throw nil; } f();", "Uncaught exception: nil")]
+    fn test_uncaught_exceptions(#[case] source: &str, #[case] message: &str) {
+        let outcome = crate::golden::interpret(source);
+
+        assert_eq!(outcome.result, Err(InterpretResult::RuntimeError));
+        assert_eq!(outcome.errors[0], message);
+    }
+
+    #[rstest]
+    fn test_step_limit_is_not_caught() {
+        let out = Capture::default();
+        let mut vm = VM::with_output(Box::new(out.clone()), Box::new(io::sink()));
+        vm.set_step_limit(Some(1000));
+
+        assert_eq!(
+            vm.interpret("try { while (true) {} } catch (e) { print e; }"),
+            Err(InterpretResult::RuntimeError)
+        );
+        assert!(out.lines().is_empty());
+    }
+
+    #[rstest]
+    #[case(
+        "import \"examples/modules/greeting.lox\" as g; print g.shout(\"x\");",
+        vec!["loading greeting", "hello, x!"]
+    )]
+    #[case(
+        "from \"examples/modules/greeting.lox\" import greet, prefix; print greet(prefix);",
+        vec!["loading greeting", "hello, hello"]
+    )]
+    #[case(
+        "import \"examples/modules/greeting.lox\" as a; import \"examples/modules/./greeting.lox\" as b; print a == b;",
+        vec!["loading greeting", "true"]
+    )]
+    #[case(
+        "var prefix = \"bye\"; import \"examples/modules/greeting.lox\" as g; print g.greet(prefix);",
+        vec!["loading greeting", "hello, bye"]
+    )]
+    #[case(
+        "{ import \"examples/modules/counter.lox\" as c; c.next(); print c.next(); }",
+        vec!["loading greeting", "2"]
+    )]
+    #[case(
+        "fun load() { from \"examples/modules/counter.lox\" import count; return count; } print load();",
+        vec!["loading greeting", "0"]
+    )]
+    #[case(
+        "try { import \"examples/modules/missing.lox\" as m; } catch (e) { print e[\"message\"]; }",
+        vec!["Module examples/modules/missing.lox not found"]
+    )]
+    fn test_modules(#[case] source: &str, #[case] expected: Vec<&str>) {
+        let outcome = crate::golden::interpret(source);
+
+        assert_eq!(outcome.result, Ok(()));
+        assert_eq!(outcome.output, expected);
+    }
+
+    #[rstest]
+    #[case("import \"missing.lox\" as m;", "Module missing.lox not found")]
+    #[case(
+        "import \"examples/modules\" as m;",
+        "Module examples/modules not found"
+    )]
+    #[case(
+        "import \"examples/modules/cycle-b.lox\" as m;",
+        "Import cycle: examples/modules/cycle-b.lox -> cycle-a.lox -> cycle-b.lox"
+    )]
+    #[case(
+        "import \"examples/modules/greeting.lox\" as m; m.missing;",
+        "Undefined variable missing in module examples/modules/greeting.lox"
+    )]
+    #[case("var m = 1; m.a;", "Only modules have properties")]
+    fn test_module_errors(#[case] source: &str, #[case] message: &str) {
+        let outcome = crate::golden::interpret(source);
+
+        assert_eq!(outcome.result, Err(InterpretResult::RuntimeError));
+        assert_eq!(outcome.errors[0], message);
+    }
+
+    #[rstest]
+    fn test_module_compile_errors_are_reported() {
+        let outcome = crate::golden::interpret("import \"examples/modules/broken.lox\" as m;");
+
+        assert_eq!(
+            outcome.errors,
+            vec![
+                "Module examples/modules/broken.lox failed to compile:",
+                "[line 1] Error at '=': Expect variable name.",
+                "[line 1] in script",
+            ]
+        );
+    }
+
+    #[rstest]
+    fn test_modules_are_found_on_the_search_path_and_cached() {
+        let out = Capture::default();
+        let mut vm = VM::with_output(Box::new(out.clone()), Box::new(io::sink()));
+        vm.set_search_path(vec![PathBuf::from("examples/modules")]);
+
+        assert_eq!(vm.interpret("import \"greeting.lox\" as g;"), Ok(()));
+        assert_eq!(vm.interpret("import \"greeting.lox\" as h;"), Ok(()));
+        assert_eq!(vm.interpret("print g.greet(\"again\");"), Ok(()));
+        assert_eq!(out.lines(), vec!["loading greeting", "hello, again"]);
+    }
+
+    #[rstest]
+    fn test_failed_module_runs_once() {
+        let outcome = crate::golden::interpret_at(
+            Some(Path::new("examples/modules/main.lox")),
+            "for (var i in 0..2) {\n\
+               try {\n\
+                 import \"failing.lox\" as failing;\n\
+               } catch (e) {\n\
+                 print e;\n\
+               }\n\
+             }",
+        );
+
+        assert_eq!(outcome.result, Ok(()));
+        assert_eq!(outcome.output, vec!["loading failing", "failed", "failed"]);
+    }
+
+    #[rstest]
+    fn test_module_stopped_by_an_uncaught_error_is_not_run_again() {
+        let out = Capture::default();
+        let mut vm = VM::with_output(Box::new(out.clone()), Box::new(io::sink()));
+        vm.set_search_path(vec![PathBuf::from("examples/modules")]);
+
+        assert!(vm.interpret("import \"failing.lox\" as f;").is_err());
+        assert!(vm.interpret("import \"failing.lox\" as f;").is_err());
+        assert_eq!(out.lines(), vec!["loading failing"]);
+    }
+
+    #[rstest]
+    fn test_imports_are_relative_to_the_script() {
+        let out = Capture::default();
+        let mut vm = VM::with_output(Box::new(out.clone()), Box::new(io::sink()));
+
+        assert_eq!(
+            vm.interpret_file(
+                Path::new("examples/modules/main.lox"),
+                "import \"counter.lox\" as c; print c.welcome(\"me\");"
+            ),
+            Ok(())
+        );
+        assert_eq!(out.lines(), vec!["loading greeting", "hello, me #1"]);
+    }
+
+    #[rstest]
+    fn test_runtime_error_reports_every_call() {
+        let outcome = crate::golden::interpret(
+            "fun inner() {\n  return nil + 1;\n}\nvar outer = () =>\n  inner();\nouter();",
+        );
+
+        assert_eq!(
+            outcome.errors,
+            vec![
+                "Both operands have to be string or number!",
+                "[line 2] in inner()",
+                "[line 5] in <lambda>()",
+                "[line 6] in script",
+            ]
+        );
+    }
+
+    #[rstest]
+    fn test_each_interpretation_runs_its_own_script() {
+        let out = Capture::default();
+        let mut vm = VM::with_output(Box::new(out.clone()), Box::new(io::sink()));
+
+        assert_eq!(vm.interpret("var a = 1; print a;"), Ok(()));
+        assert_eq!(vm.interpret("print a + 1;"), Ok(()));
+        assert!(vm.interpret("print nil + 1;").is_err());
+        assert_eq!(vm.interpret("print a + 2;"), Ok(()));
+        assert_eq!(out.lines(), vec!["1", "2", "3"]);
+    }
+
+    #[rstest]
+    fn test_constants_are_guarded_across_interpretations() {
+        let out = Capture::default();
+        let err = Capture::default();
+        let mut vm = VM::with_output(Box::new(out.clone()), Box::new(err.clone()));
+
+        assert_eq!(vm.interpret("const a = 1;"), Ok(()));
+        assert!(vm.interpret("a = 2;").is_err());
+        assert!(vm.interpret("var a = 3;").is_err());
+        assert!(vm.interpret("const a = 4;").is_err());
+        assert_eq!(vm.interpret("print a;"), Ok(()));
+        assert_eq!(out.lines(), vec!["1"]);
+        assert_eq!(
+            err.lines(),
+            vec![
+                "Can't assign to constant a",
+                "[line 1] in script",
+                "Constant a is already defined",
+                "[line 1] in script",
+                "Constant a is already defined",
+                "[line 1] in script",
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case("print 1 + 2;", Ok(()))]
+    #[case("print 1 +;", Err(InterpretResult::CompilerError))]
+    #[case("print -\"a\";", Err(InterpretResult::RuntimeError))]
+    fn test_interpret_result(#[case] source: &str, #[case] expected: Result<(), InterpretResult>) {
+        let mut vm = VM::new();
+        assert_eq!(vm.interpret(source), expected);
+    }
 }
@@ -0,0 +1,15 @@
+use std::process::Command;
+
+#[test]
+fn examples_match_expectations() {
+    let output = Command::new(env!("CARGO_BIN_EXE_lox-vm"))
+        .args(["test", "examples"])
+        .output()
+        .expect("Could not run the interpreter");
+
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+}